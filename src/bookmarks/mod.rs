@@ -0,0 +1,1051 @@
+use errors::*;
+use sha1::{Digest, Sha1};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use sync::{Bookmark, Bookmarks};
+use {Host, HostKind};
+
+/// A kind of bookmark output. Each writer decides what its files are
+/// called and how the hosts' names and URLs are escaped in them.
+pub trait BookmarkWriter {
+    /// Returns the files that bookmark `hosts`.
+    fn bookmarks(&self, hosts: &[&Host]) -> Result<Bookmarks>;
+}
+
+/// A kind of bookmark file that holds a single host.
+pub trait BookmarkFile {
+    /// Returns the name of the bookmark file for `host`.
+    fn filename(&self, host: &Host) -> Result<String>;
+
+    /// Returns the contents of the bookmark file for `host`.
+    fn contents(&self, host: &Host) -> String;
+}
+
+impl<T: BookmarkFile> BookmarkWriter for T {
+    /// If several hosts end up with the same file name, the last one
+    /// wins, just like when writing them out one after the other.
+    fn bookmarks(&self, hosts: &[&Host]) -> Result<Bookmarks> {
+        let mut bookmarks = Bookmarks::new();
+        for host in hosts {
+            bookmarks.insert(
+                self.filename(host)?,
+                Bookmark {
+                    url: Some(host.url()),
+                    contents: self.contents(host),
+                },
+            );
+        }
+        Ok(bookmarks)
+    }
+}
+
+/// How hosts get sorted into folders.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Grouping {
+    /// By the name of the file that they were found in.
+    Source,
+    /// By protocol.
+    Protocol,
+    /// By the domain that their name is in, like `prod.example.com`
+    /// for `db1.prod.example.com`.
+    Domain,
+    /// By their ssh_config `Tag`.
+    Tag,
+}
+
+impl Grouping {
+    pub fn from_name(name: &str) -> Result<Grouping> {
+        match name {
+            "source" => Ok(Grouping::Source),
+            "protocol" => Ok(Grouping::Protocol),
+            "domain" => Ok(Grouping::Domain),
+            "tag" => Ok(Grouping::Tag),
+            _ => bail!(ErrorKind::UnknownGrouping(name.to_string())),
+        }
+    }
+
+    /// Returns the name of the folder that `host` goes in, or `None`
+    /// if it stays at the top level: hosts without a domain or a tag
+    /// do.
+    pub fn folder(self, host: &Host) -> Option<String> {
+        let folder = match self {
+            Grouping::Source => host
+                .from
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned()),
+            Grouping::Protocol => Some(host.protocol.clone()),
+            Grouping::Domain if host.kind() != HostKind::Name => None,
+            Grouping::Domain => host
+                .name
+                .split_once('.')
+                .map(|(_, domain)| domain.to_string()),
+            Grouping::Tag => host.tag.clone(),
+        };
+        // Folders are directories too, so they can't contain slashes
+        // or point elsewhere:
+        folder
+            .map(|f| f.replace('/', "_"))
+            .filter(|f| !["", ".", ".."].contains(&f.as_str()))
+    }
+}
+
+/// Puts the bookmark files of another writer into a folder for each
+/// group of hosts.
+pub struct Grouped<W> {
+    writer: W,
+    group_by: Grouping,
+}
+
+impl<W: BookmarkFile> BookmarkWriter for Grouped<W> {
+    fn bookmarks(&self, hosts: &[&Host]) -> Result<Bookmarks> {
+        let mut bookmarks = Bookmarks::new();
+        for host in hosts {
+            let path = match self.group_by.folder(host) {
+                Some(folder) if self.group_by == Grouping::Domain => {
+                    // The folder already says what the domain is:
+                    let mut short = (*host).clone();
+                    short.name = host.name.split('.').next().unwrap_or("").to_string();
+                    format!("{}/{}", folder, self.writer.filename(&short)?)
+                }
+                Some(folder) => format!("{}/{}", folder, self.writer.filename(host)?),
+                None => self.writer.filename(host)?,
+            };
+            bookmarks.insert(
+                path,
+                Bookmark {
+                    url: Some(host.url()),
+                    contents: self.writer.contents(host),
+                },
+            );
+        }
+        Ok(bookmarks)
+    }
+}
+
+/// Returns `writer`, grouping its files into folders if `options` say
+/// so.
+fn per_host<W: BookmarkFile + 'static>(writer: W, options: &Options) -> Box<dyn BookmarkWriter> {
+    match options.group_by {
+        Some(group_by) => Box::new(Grouped { writer, group_by }),
+        None => Box::new(writer),
+    }
+}
+
+/// Settings that some of the writers use.
+#[derive(Debug, Default)]
+pub struct Options {
+    /// The command line that runs a program in a terminal emulator,
+    /// like `xterm -e`.
+    pub terminal: Option<String>,
+    /// The name of the terminal profile that generated profiles are
+    /// based on.
+    pub parent_profile: Option<String>,
+    /// How to sort hosts into folders.
+    pub group_by: Option<Grouping>,
+}
+
+/// Returns the writer for the bookmark format called `name`.
+pub fn writer(name: &str, options: &Options) -> Result<Box<dyn BookmarkWriter>> {
+    match name {
+        "webloc" => Ok(per_host(Webloc, options)),
+        "inetloc" => Ok(per_host(Inetloc, options)),
+        "desktop" => Ok(per_host(DesktopLink, options)),
+        "desktop-launcher" => Ok(per_host(DesktopLauncher::new(options), options)),
+        "url" => Ok(per_host(InternetShortcut, options)),
+        "iterm2-dynamic-profiles" => Ok(Box::new(ITerm2Profiles::new(options))),
+        "windows-terminal" => Ok(Box::new(WindowsTerminalFragment)),
+        "kitty-session" => Ok(per_host(KittySession, options)),
+        "wezterm-ssh-domains" => Ok(Box::new(WezTermDomains)),
+        "html" => Ok(Box::new(NetscapeBookmarks::new(options))),
+        _ => bail!(ErrorKind::UnknownFormat(name.to_string())),
+    }
+}
+
+/// The `name (protocol).extension` file name that all the writers
+/// use, with the characters in `reserved` replaced by underscores.
+fn display_filename(host: &Host, extension: &str, reserved: &[char]) -> Result<String> {
+    let name = format!("{} ({})", host.name, host.protocol);
+    if Path::new(&name).is_absolute() {
+        bail!(ErrorKind::NameError(
+            host.name.to_string(),
+            host.protocol.to_string()
+        ));
+    }
+    let name: String = name
+        .chars()
+        .map(|c| {
+            if reserved.contains(&c) || c.is_control() {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect();
+    Ok(format!("{}.{}", name, extension))
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn plist(url: &str) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0"><dict><key>URL</key><string>{}</string></dict></plist>
+"#,
+        xml_escape(url)
+    )
+}
+
+/// The characters that can't be in macOS file names: `/` separates
+/// folders, and the Finder shows `:` as one too.
+const MACOS_RESERVED: &[char] = &['/', ':'];
+
+/// macOS `.webloc` files, which Finder, Alfred and LaunchBar open.
+pub struct Webloc;
+
+impl BookmarkFile for Webloc {
+    fn filename(&self, host: &Host) -> Result<String> {
+        display_filename(host, "webloc", MACOS_RESERVED)
+    }
+
+    fn contents(&self, host: &Host) -> String {
+        plist(&host.url())
+    }
+}
+
+/// macOS `.inetloc` files, the Finder's format for locations that
+/// aren't web pages.
+pub struct Inetloc;
+
+impl BookmarkFile for Inetloc {
+    fn filename(&self, host: &Host) -> Result<String> {
+        display_filename(host, "inetloc", MACOS_RESERVED)
+    }
+
+    fn contents(&self, host: &Host) -> String {
+        plist(&host.url())
+    }
+}
+
+/// Escapes a value in a freedesktop desktop entry file.
+pub fn desktop_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            '\r' => escaped.push_str("\\r"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// freedesktop `.desktop` files of `Type=Link`, which Linux file
+/// managers open.
+pub struct DesktopLink;
+
+impl BookmarkFile for DesktopLink {
+    fn filename(&self, host: &Host) -> Result<String> {
+        display_filename(host, "desktop", &['/'])
+    }
+
+    fn contents(&self, host: &Host) -> String {
+        format!(
+            "[Desktop Entry]\nType=Link\nName={}\nURL={}\nIcon=utilities-terminal\n",
+            desktop_escape(&format!("{} ({})", host.name, host.protocol)),
+            desktop_escape(&host.url())
+        )
+    }
+}
+
+/// Quotes an argument in the `Exec` key of a desktop entry.
+fn exec_quote(arg: &str) -> String {
+    const RESERVED: &[char] = &[
+        ' ', '\t', '\n', '"', '\'', '\\', '>', '<', '~', '|', '&', ';', '$', '*', '?', '#', '(',
+        ')', '`', '=',
+    ];
+    let arg = arg.replace('%', "%%");
+    if !arg.is_empty() && !arg.contains(RESERVED) {
+        return arg;
+    }
+    let mut quoted = String::from("\"");
+    for c in arg.chars() {
+        if ['"', '`', '$', '\\'].contains(&c) {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Escapes an item of a list value in a desktop entry.
+fn desktop_list_item(text: &str) -> String {
+    desktop_escape(text).replace(';', "\\;")
+}
+
+/// freedesktop `.desktop` files of `Type=Application` that open a
+/// terminal running ssh or mosh, so that application launchers find
+/// the hosts. They belong in `~/.local/share/applications/`.
+pub struct DesktopLauncher {
+    terminal: Vec<String>,
+}
+
+impl DesktopLauncher {
+    pub fn new(options: &Options) -> DesktopLauncher {
+        DesktopLauncher {
+            terminal: options
+                .terminal
+                .as_ref()
+                .map(|t| t.split_whitespace().map(|w| w.to_string()).collect())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+impl BookmarkFile for DesktopLauncher {
+    fn filename(&self, host: &Host) -> Result<String> {
+        // Desktop file IDs should only use the characters of D-Bus
+        // names:
+        let name: String = format!("{}-{}", host.protocol, host.name)
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' || c == '.' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        Ok(format!("{}.desktop", name))
+    }
+
+    fn contents(&self, host: &Host) -> String {
+        let mut command = self.terminal.clone();
+        command.extend(host.command());
+        let exec: Vec<String> = command.iter().map(|arg| exec_quote(arg)).collect();
+
+        let mut keywords = vec![host.name.clone(), host.protocol.clone()];
+        keywords.extend(host.hostname.clone());
+        keywords.extend(host.user.clone());
+        let keywords: String = keywords
+            .iter()
+            .map(|k| desktop_list_item(k) + ";")
+            .collect();
+
+        format!(
+            "[Desktop Entry]\n\
+             Type=Application\n\
+             Name={}\n\
+             Comment={}\n\
+             Exec={}\n\
+             Terminal={}\n\
+             Icon=utilities-terminal\n\
+             Keywords={}\n\
+             Categories=Network;RemoteAccess;\n",
+            desktop_escape(&format!("{} ({})", host.name, host.protocol)),
+            desktop_escape(&host.from.display().to_string()),
+            desktop_escape(&exec.join(" ")),
+            self.terminal.is_empty(),
+            keywords
+        )
+    }
+}
+
+/// Windows `.url` Internet Shortcut files.
+pub struct InternetShortcut;
+
+impl BookmarkFile for InternetShortcut {
+    fn filename(&self, host: &Host) -> Result<String> {
+        display_filename(host, "url", &['<', '>', ':', '"', '/', '\\', '|', '?', '*'])
+    }
+
+    fn contents(&self, host: &Host) -> String {
+        // Line breaks can't be escaped in these files, so they're
+        // dropped from the URL:
+        let url: String = host.url().chars().filter(|c| !c.is_control()).collect();
+        format!("[InternetShortcut]\r\nURL={}\r\n", url)
+    }
+}
+
+/// Returns an identifier for `host` in the form of a UUID (a version
+/// 5 one in the URL namespace) that stays the same as long as its name
+/// and protocol do.
+pub fn stable_id(host: &Host) -> String {
+    const URL_NAMESPACE: [u8; 16] = [
+        0x6b, 0xa7, 0xb8, 0x11, 0x9d, 0xad, 0x11, 0xd1, 0x80, 0xb4, 0x00, 0xc0, 0x4f, 0xd4, 0x30,
+        0xc8,
+    ];
+    let mut hasher = Sha1::new();
+    hasher.input(URL_NAMESPACE);
+    hasher.input(format!("{}://{}", host.protocol, host.name).as_bytes());
+    let mut id = [0u8; 16];
+    id.copy_from_slice(&hasher.result()[..16]);
+    id[6] = (id[6] & 0x0f) | 0x50;
+    id[8] = (id[8] & 0x3f) | 0x80;
+    let hex: Vec<String> = id.iter().map(|b| format!("{:02X}", b)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        hex[0..4].concat(),
+        hex[4..6].concat(),
+        hex[6..8].concat(),
+        hex[8..10].concat(),
+        hex[10..16].concat()
+    )
+}
+
+/// Sorts `hosts` into one group per `stable_id`, in the order that
+/// each id first comes up: a host can be in more than one file, like
+/// in an ssh_config and a known_hosts file, but it should only get one
+/// profile.
+fn by_stable_id<'a>(hosts: &[&'a Host]) -> Vec<Vec<&'a Host>> {
+    let mut groups: Vec<Vec<&'a Host>> = vec![];
+    let mut index: HashMap<(&str, &str), usize> = HashMap::new();
+    for &host in hosts {
+        let key = (host.name.as_str(), host.protocol.as_str());
+        match index.get(&key) {
+            Some(&i) => groups[i].push(host),
+            None => {
+                index.insert(key, groups.len());
+                groups.push(vec![host]);
+            }
+        }
+    }
+    groups
+}
+
+/// Returns the host of a `by_stable_id` group whose connection
+/// settings a profile gets: the first one that isn't from known_hosts,
+/// which has none.
+fn primary<'a>(group: &[&'a Host]) -> &'a Host {
+    group
+        .iter()
+        .find(|h| !h.known_host)
+        .cloned()
+        .unwrap_or(group[0])
+}
+
+/// Quotes `args` for a POSIX shell.
+pub fn shell_words(args: &[String]) -> String {
+    let quoted: Vec<String> = args
+        .iter()
+        .map(|arg| {
+            let plain = |c: char| c.is_ascii_alphanumeric() || "@%+=:,./-_".contains(c);
+            if !arg.is_empty() && arg.chars().all(plain) {
+                arg.to_string()
+            } else {
+                format!("'{}'", arg.replace('\'', "'\\''"))
+            }
+        })
+        .collect();
+    quoted.join(" ")
+}
+
+/// An iTerm2 Dynamic Profiles file, with a profile that runs ssh or
+/// mosh for each host. It belongs in
+/// `~/Library/Application Support/iTerm2/DynamicProfiles`.
+pub struct ITerm2Profiles {
+    parent: Option<String>,
+}
+
+impl ITerm2Profiles {
+    pub const FILENAME: &'static str = "ssh_bookmarker.json";
+
+    pub fn new(options: &Options) -> ITerm2Profiles {
+        ITerm2Profiles {
+            parent: options.parent_profile.clone(),
+        }
+    }
+}
+
+impl BookmarkWriter for ITerm2Profiles {
+    fn bookmarks(&self, hosts: &[&Host]) -> Result<Bookmarks> {
+        let profiles: Vec<_> = by_stable_id(hosts)
+            .iter()
+            .map(|group| {
+                let host = primary(group);
+                // Tagged with all the files that the host is in:
+                let mut tags: Vec<String> = vec![];
+                for source in group.iter().map(|h| h.from.display().to_string()) {
+                    if !tags.contains(&source) {
+                        tags.push(source);
+                    }
+                }
+                tags.push(host.protocol.clone());
+                let mut profile = json!({
+                    "Name": format!("{} ({})", host.name, host.protocol),
+                    "Guid": stable_id(host),
+                    "Custom Command": "Yes",
+                    "Command": shell_words(&host.command()),
+                    "Tags": tags,
+                });
+                if let Some(ref parent) = self.parent {
+                    profile["Dynamic Profile Parent Name"] = json!(parent);
+                }
+                profile
+            })
+            .collect();
+        let mut bookmarks = Bookmarks::new();
+        bookmarks.insert(
+            ITerm2Profiles::FILENAME.to_string(),
+            Bookmark {
+                url: None,
+                contents: format!("{:#}\n", json!({ "Profiles": profiles })),
+            },
+        );
+        Ok(bookmarks)
+    }
+}
+
+/// Quotes `args` the way `CommandLineToArgvW` splits them on Windows.
+pub fn windows_command_line(args: &[String]) -> String {
+    let quoted: Vec<String> = args
+        .iter()
+        .map(|arg| {
+            if !arg.is_empty() && !arg.contains(&[' ', '\t', '"'][..]) {
+                return arg.to_string();
+            }
+            let mut quoted = String::from("\"");
+            let mut backslashes = 0;
+            for c in arg.chars() {
+                match c {
+                    '\\' => backslashes += 1,
+                    '"' => {
+                        quoted.push_str(&"\\".repeat(2 * backslashes + 1));
+                        backslashes = 0;
+                    }
+                    _ => {
+                        quoted.push_str(&"\\".repeat(backslashes));
+                        backslashes = 0;
+                    }
+                }
+                if c != '\\' {
+                    quoted.push(c);
+                }
+            }
+            quoted.push_str(&"\\".repeat(2 * backslashes));
+            quoted.push('"');
+            quoted
+        })
+        .collect();
+    quoted.join(" ")
+}
+
+/// A Windows Terminal JSON fragment with a profile that runs ssh or
+/// mosh for each host. It belongs in a directory under
+/// `%LOCALAPPDATA%\\Microsoft\\Windows Terminal\\Fragments`.
+pub struct WindowsTerminalFragment;
+
+impl WindowsTerminalFragment {
+    pub const FILENAME: &'static str = "ssh_bookmarker.json";
+}
+
+impl BookmarkWriter for WindowsTerminalFragment {
+    fn bookmarks(&self, hosts: &[&Host]) -> Result<Bookmarks> {
+        let profiles: Vec<_> = by_stable_id(hosts)
+            .iter()
+            .map(|group| {
+                let host = primary(group);
+                json!({
+                    "name": format!("{} ({})", host.name, host.protocol),
+                    "guid": format!("{{{}}}", stable_id(host).to_lowercase()),
+                    "commandline": windows_command_line(&host.command()),
+                })
+            })
+            .collect();
+        let mut bookmarks = Bookmarks::new();
+        bookmarks.insert(
+            WindowsTerminalFragment::FILENAME.to_string(),
+            Bookmark {
+                url: None,
+                contents: format!("{:#}\n", json!({ "profiles": profiles })),
+            },
+        );
+        Ok(bookmarks)
+    }
+}
+
+/// kitty session files that open a tab running ssh or mosh, one per
+/// host, for `kitty --session`.
+pub struct KittySession;
+
+impl BookmarkFile for KittySession {
+    fn filename(&self, host: &Host) -> Result<String> {
+        display_filename(host, "kitty-session", &['/'])
+    }
+
+    fn contents(&self, host: &Host) -> String {
+        // Session files are line based, and tab titles can't be quoted:
+        let title: String = format!("{} ({})", host.name, host.protocol)
+            .chars()
+            .filter(|c| !c.is_control())
+            .collect();
+        let mut launch = vec![
+            "launch".to_string(),
+            "--title".to_string(),
+            title.clone(),
+            "--var".to_string(),
+            format!("ssh_bookmarker_id={}", stable_id(host)),
+        ];
+        launch.extend(host.command());
+        format!("new_tab {}\n{}\n", title, shell_words(&launch))
+    }
+}
+
+/// Quotes `text` as a Lua string literal.
+fn lua_string(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '\\' => quoted.push_str("\\\\"),
+            '"' => quoted.push_str("\\\""),
+            '\n' => quoted.push_str("\\n"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            _ => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// A Lua file that returns a WezTerm `ssh_domains` table with a domain
+/// for each ssh host. WezTerm has no mosh support, so hosts with other
+/// protocols are left out.
+pub struct WezTermDomains;
+
+impl WezTermDomains {
+    pub const FILENAME: &'static str = "ssh_domains.lua";
+}
+
+impl BookmarkWriter for WezTermDomains {
+    fn bookmarks(&self, hosts: &[&Host]) -> Result<Bookmarks> {
+        let mut contents = String::from("-- Generated by ssh_bookmarker.\nreturn {\n");
+        let ssh_hosts: Vec<&Host> = hosts
+            .iter()
+            .filter(|h| h.protocol == "ssh")
+            .cloned()
+            .collect();
+        for group in by_stable_id(&ssh_hosts) {
+            let host = primary(&group);
+            let mut address = if host.name.contains(':') {
+                format!("[{}]", host.name)
+            } else {
+                host.name.clone()
+            };
+            if let Some(port) = host.port {
+                address.push_str(&format!(":{}", port));
+            }
+            contents.push_str(&format!(
+                "  {{\n    name = {},\n    remote_address = {},\n",
+                lua_string(&host.name),
+                lua_string(&address)
+            ));
+            if let Some(ref user) = host.user {
+                contents.push_str(&format!("    username = {},\n", lua_string(user)));
+            }
+            contents.push_str("    multiplexing = \"None\",\n  },\n");
+        }
+        contents.push_str("}\n");
+
+        let mut bookmarks = Bookmarks::new();
+        bookmarks.insert(
+            WezTermDomains::FILENAME.to_string(),
+            Bookmark {
+                url: None,
+                contents,
+            },
+        );
+        Ok(bookmarks)
+    }
+}
+
+/// A Netscape bookmark file (`bookmarks.html`), which browsers can
+/// import, with a folder for each group of hosts (by default, for each
+/// source file).
+pub struct NetscapeBookmarks {
+    group_by: Grouping,
+}
+
+impl NetscapeBookmarks {
+    pub const FILENAME: &'static str = "bookmarks.html";
+
+    pub fn new(options: &Options) -> NetscapeBookmarks {
+        NetscapeBookmarks {
+            group_by: options.group_by.unwrap_or(Grouping::Source),
+        }
+    }
+}
+
+impl BookmarkWriter for NetscapeBookmarks {
+    fn bookmarks(&self, hosts: &[&Host]) -> Result<Bookmarks> {
+        let mut folders: BTreeMap<Option<String>, Vec<&Host>> = BTreeMap::new();
+        for host in hosts {
+            folders
+                .entry(self.group_by.folder(host))
+                .or_default()
+                .push(host);
+        }
+
+        let mut contents = String::from(
+            "<!DOCTYPE NETSCAPE-Bookmark-file-1>\n\
+             <META HTTP-EQUIV=\"Content-Type\" CONTENT=\"text/html; charset=UTF-8\">\n\
+             <TITLE>Bookmarks</TITLE>\n\
+             <H1>Bookmarks</H1>\n\
+             <DL><p>\n",
+        );
+        for (folder, hosts) in folders {
+            let indent = if folder.is_some() { "    " } else { "" };
+            if let Some(ref folder) = folder {
+                contents.push_str(&format!(
+                    "    <DT><H3>{}</H3>\n    <DL><p>\n",
+                    xml_escape(folder)
+                ));
+            }
+            for host in hosts {
+                contents.push_str(&format!(
+                    "{}    <DT><A HREF=\"{}\">{} ({})</A>\n",
+                    indent,
+                    xml_escape(&host.url()),
+                    xml_escape(&host.name),
+                    xml_escape(&host.protocol)
+                ));
+            }
+            if folder.is_some() {
+                contents.push_str("    </DL><p>\n");
+            }
+        }
+        contents.push_str("</DL><p>\n");
+
+        let mut bookmarks = Bookmarks::new();
+        bookmarks.insert(
+            NetscapeBookmarks::FILENAME.to_string(),
+            Bookmark {
+                url: None,
+                contents,
+            },
+        );
+        Ok(bookmarks)
+    }
+}
+
+#[test]
+fn test_writers() {
+    let from = Path::new("/dev/null");
+    let mut host = Host::new("fe80::1%en0", "mosh", from);
+    host.user = Some("R&D".to_string());
+
+    assert_eq!(Webloc.filename(&host).unwrap(), "fe80__1%en0 (mosh).webloc");
+    assert_eq!(
+        Webloc.filename(&Host::named("../../escape", from)).unwrap(),
+        ".._.._escape (ssh).webloc"
+    );
+    assert!(Webloc
+        .contents(&host)
+        .contains("<string>mosh://R&amp;D@[fe80::1%25en0]</string>"));
+    assert_eq!(
+        Inetloc.filename(&host).unwrap(),
+        "fe80__1%en0 (mosh).inetloc"
+    );
+
+    assert_eq!(
+        DesktopLink.filename(&Host::named("a/b", from)).unwrap(),
+        "a_b (ssh).desktop"
+    );
+    assert_eq!(
+        DesktopLink.contents(&Host::named("back\\slash", from)),
+        "[Desktop Entry]\nType=Link\nName=back\\\\slash (ssh)\n\
+         URL=ssh://back\\\\slash\nIcon=utilities-terminal\n"
+    );
+
+    assert_eq!(
+        InternetShortcut.filename(&host).unwrap(),
+        "fe80__1%en0 (mosh).url"
+    );
+    assert_eq!(
+        InternetShortcut.contents(&host),
+        "[InternetShortcut]\r\nURL=mosh://R&D@[fe80::1%25en0]\r\n"
+    );
+
+    assert!(Webloc.filename(&Host::named("/etc/passwd", from)).is_err());
+    assert!(writer("desktop", &Options::default()).is_ok());
+    assert!(writer("gopher", &Options::default()).is_err());
+}
+
+#[test]
+fn test_desktop_launcher() {
+    let from = Path::new("/home/me/.ssh/config");
+    let mut host = Host::new("db;1", "mosh", from);
+    host.user = Some("me".to_string());
+    host.port = Some(2222);
+
+    let launcher = DesktopLauncher::new(&Options::default());
+    assert_eq!(launcher.filename(&host).unwrap(), "mosh-db_1.desktop");
+    assert_eq!(
+        launcher.contents(&host),
+        "[Desktop Entry]\n\
+         Type=Application\n\
+         Name=db;1 (mosh)\n\
+         Comment=/home/me/.ssh/config\n\
+         Exec=mosh \"--ssh=ssh -p 2222\" -- \"me@db;1\"\n\
+         Terminal=true\n\
+         Icon=utilities-terminal\n\
+         Keywords=db\\;1;mosh;me;\n\
+         Categories=Network;RemoteAccess;\n"
+    );
+
+    let launcher = DesktopLauncher::new(&Options {
+        terminal: Some("xterm -e".to_string()),
+        ..Options::default()
+    });
+    let contents = launcher.contents(&Host::named("100%$ure", from));
+    assert!(contents.contains("\nExec=xterm -e ssh -- \"100%%\\\\$ure\"\n"));
+    assert!(contents.contains("\nTerminal=false\n"));
+}
+
+#[test]
+fn test_stable_id() {
+    let from = Path::new("/dev/null");
+    let id = stable_id(&Host::named("ohai", from));
+    assert_eq!(id.len(), 36);
+    assert_eq!(&id[14..15], "5");
+    assert_eq!(id, stable_id(&Host::named("ohai", Path::new("/etc/hosts"))));
+    assert!(id != stable_id(&Host::new("ohai", "mosh", from)));
+    assert!(id != stable_id(&Host::named("ohai2", from)));
+}
+
+#[test]
+fn test_iterm2_profiles() {
+    let from = Path::new("/home/me/.ssh/config");
+    let mut bastion = Host::new("bastion", "mosh", from);
+    bastion.port = Some(2222);
+    let ohai = Host::named("ohai", from);
+    let writer = ITerm2Profiles::new(&Options {
+        parent_profile: Some("Remote".to_string()),
+        ..Options::default()
+    });
+    let bookmarks = writer.bookmarks(&[&bastion, &ohai]).unwrap();
+    assert_eq!(bookmarks.len(), 1);
+    let profiles: ::serde_json::Value =
+        ::serde_json::from_str(&bookmarks[ITerm2Profiles::FILENAME].contents).unwrap();
+    assert_eq!(
+        profiles["Profiles"][0],
+        json!({
+            "Name": "bastion (mosh)",
+            "Guid": stable_id(&bastion),
+            "Custom Command": "Yes",
+            "Command": "mosh '--ssh=ssh -p 2222' -- bastion",
+            "Tags": ["/home/me/.ssh/config", "mosh"],
+            "Dynamic Profile Parent Name": "Remote",
+        })
+    );
+    assert_eq!(profiles["Profiles"][1]["Command"], "ssh -- ohai");
+
+    // A host from several files gets one profile, with the settings
+    // from its ssh_config:
+    let known = Path::new("/home/me/.ssh/known_hosts");
+    let mut web = Host::named("web", known);
+    web.known_host = true;
+    let mut configured = Host::named("web", from);
+    configured.user = Some("deploy".to_string());
+    let bookmarks = ITerm2Profiles::new(&Options::default())
+        .bookmarks(&[&web, &configured, &ohai])
+        .unwrap();
+    let profiles: ::serde_json::Value =
+        ::serde_json::from_str(&bookmarks[ITerm2Profiles::FILENAME].contents).unwrap();
+    let profiles = profiles["Profiles"].as_array().unwrap();
+    assert_eq!(profiles.len(), 2);
+    assert_eq!(profiles[0]["Guid"], stable_id(&web));
+    assert_eq!(profiles[0]["Command"], "ssh -- deploy@web");
+    assert_eq!(
+        profiles[0]["Tags"],
+        json!(["/home/me/.ssh/known_hosts", "/home/me/.ssh/config", "ssh"])
+    );
+}
+
+#[test]
+fn test_shell_words() {
+    let args: Vec<String> = vec!["ssh", "-p", "22", "it's me@host", ""]
+        .into_iter()
+        .map(|a| a.to_string())
+        .collect();
+    assert_eq!(shell_words(&args), "ssh -p 22 'it'\\''s me@host' ''");
+}
+
+#[test]
+fn test_windows_terminal_fragment() {
+    let from = Path::new("/dev/null");
+    let mut host = Host::named("db 1", from);
+    host.user = Some("me".to_string());
+    let bookmarks = WindowsTerminalFragment.bookmarks(&[&host]).unwrap();
+    let fragment: ::serde_json::Value =
+        ::serde_json::from_str(&bookmarks[WindowsTerminalFragment::FILENAME].contents).unwrap();
+    assert_eq!(
+        fragment,
+        json!({"profiles": [{
+            "name": "db 1 (ssh)",
+            "guid": format!("{{{}}}", stable_id(&host).to_lowercase()),
+            "commandline": "ssh -- \"me@db 1\"",
+        }]})
+    );
+
+    let mut known = Host::named("db 1", Path::new("/etc/ssh/ssh_known_hosts"));
+    known.known_host = true;
+    let bookmarks = WindowsTerminalFragment.bookmarks(&[&known, &host]).unwrap();
+    let fragment: ::serde_json::Value =
+        ::serde_json::from_str(&bookmarks[WindowsTerminalFragment::FILENAME].contents).unwrap();
+    assert_eq!(fragment["profiles"].as_array().unwrap().len(), 1);
+    assert_eq!(fragment["profiles"][0]["commandline"], "ssh -- \"me@db 1\"");
+
+    let args: Vec<String> = vec!["a\\b", "say \"hi\"", "C:\\Program Files\\", ""]
+        .into_iter()
+        .map(|a| a.to_string())
+        .collect();
+    assert_eq!(
+        windows_command_line(&args),
+        r#"a\b "say \"hi\"" "C:\Program Files\\" """#
+    );
+}
+
+#[test]
+fn test_kitty_session() {
+    let from = Path::new("/dev/null");
+    let mut host = Host::new("web", "mosh", from);
+    host.port = Some(2222);
+    assert_eq!(
+        KittySession.filename(&host).unwrap(),
+        "web (mosh).kitty-session"
+    );
+    assert_eq!(
+        KittySession.contents(&host),
+        format!(
+            "new_tab web (mosh)\n\
+             launch --title 'web (mosh)' --var ssh_bookmarker_id={} \
+             mosh '--ssh=ssh -p 2222' -- web\n",
+            stable_id(&host)
+        )
+    );
+}
+
+#[test]
+fn test_wezterm_domains() {
+    let from = Path::new("/dev/null");
+    let mut web = Host::named("web", from);
+    web.user = Some("me".to_string());
+    web.port = Some(2222);
+    let v6 = Host::named("fe80::1%en0", from);
+    let mosh = Host::new("web", "mosh", from);
+    let quoted = Host::named("a\"b", from);
+    let mut known = Host::named("web", Path::new("/etc/ssh/ssh_known_hosts"));
+    known.known_host = true;
+    let bookmarks = WezTermDomains
+        .bookmarks(&[&known, &web, &v6, &mosh, &quoted])
+        .unwrap();
+    assert_eq!(
+        bookmarks[WezTermDomains::FILENAME].contents,
+        r#"-- Generated by ssh_bookmarker.
+return {
+  {
+    name = "web",
+    remote_address = "web:2222",
+    username = "me",
+    multiplexing = "None",
+  },
+  {
+    name = "fe80::1%en0",
+    remote_address = "[fe80::1%en0]",
+    multiplexing = "None",
+  },
+  {
+    name = "a\"b",
+    remote_address = "a\"b",
+    multiplexing = "None",
+  },
+}
+"#
+    );
+}
+
+#[test]
+fn test_netscape_bookmarks() {
+    let config = Path::new("/etc/ssh/ssh_config");
+    let known = Path::new("/etc/ssh/ssh_known_hosts");
+    let mut odd = Host::named("<b>&co", known);
+    odd.user = Some("\"me\"".to_string());
+    let web = Host::new("web", "mosh", config);
+    let db = Host::named("db", config);
+
+    let by_source = NetscapeBookmarks::new(&Options::default());
+    let bookmarks = by_source.bookmarks(&[&odd, &web, &db]).unwrap();
+    let contents = &bookmarks[NetscapeBookmarks::FILENAME].contents;
+    assert!(contents.ends_with(
+        "<DL><p>\n\
+         \x20   <DT><H3>ssh_config</H3>\n\
+         \x20   <DL><p>\n\
+         \x20       <DT><A HREF=\"mosh://web\">web (mosh)</A>\n\
+         \x20       <DT><A HREF=\"ssh://db\">db (ssh)</A>\n\
+         \x20   </DL><p>\n\
+         \x20   <DT><H3>ssh_known_hosts</H3>\n\
+         \x20   <DL><p>\n\
+         \x20       <DT><A HREF=\"ssh://&quot;me&quot;@&lt;b&gt;&amp;co\">\
+         &lt;b&gt;&amp;co (ssh)</A>\n\
+         \x20   </DL><p>\n\
+         </DL><p>\n"
+    ));
+
+    let by_protocol = NetscapeBookmarks::new(&Options {
+        group_by: Some(Grouping::Protocol),
+        ..Options::default()
+    });
+    let bookmarks = by_protocol.bookmarks(&[&odd, &web, &db]).unwrap();
+    let contents = &bookmarks[NetscapeBookmarks::FILENAME].contents;
+    let mosh = contents.find("<H3>mosh</H3>").unwrap();
+    let ssh = contents.find("<H3>ssh</H3>").unwrap();
+    assert!(mosh < contents.find("mosh://web").unwrap());
+    assert!(ssh < contents.find("ssh://db").unwrap());
+    assert!(Grouping::from_name("color").is_err());
+}
+
+#[test]
+fn test_grouping() {
+    let from = Path::new("/home/me/.ssh/config");
+    let mut db = Host::named("db1.prod.example.com", from);
+    db.tag = Some("databases".to_string());
+    let local = Host::new("localhost", "mosh", from);
+    let ip = Host::named("192.0.2.53", from);
+
+    assert_eq!(Grouping::Source.folder(&db), Some("config".to_string()));
+    assert_eq!(Grouping::Protocol.folder(&local), Some("mosh".to_string()));
+    assert_eq!(
+        Grouping::Domain.folder(&db),
+        Some("prod.example.com".to_string())
+    );
+    assert_eq!(Grouping::Domain.folder(&local), None);
+    assert_eq!(Grouping::Domain.folder(&ip), None);
+    assert_eq!(Grouping::Tag.folder(&db), Some("databases".to_string()));
+    assert_eq!(Grouping::Tag.folder(&local), None);
+    assert_eq!(Grouping::Domain.folder(&Host::named("a..", from)), None);
+
+    let writer = writer(
+        "webloc",
+        &Options {
+            group_by: Some(Grouping::Domain),
+            ..Options::default()
+        },
+    )
+    .unwrap();
+    let bookmarks = writer.bookmarks(&[&db, &local]).unwrap();
+    assert_eq!(
+        bookmarks.keys().collect::<Vec<_>>(),
+        vec![
+            "localhost (mosh).webloc",
+            "prod.example.com/db1 (ssh).webloc"
+        ]
+    );
+}
@@ -0,0 +1,83 @@
+use bookmarks::shell_words;
+use errors::*;
+
+/// The commands whose host names get completed.
+const COMMANDS: &[&str] = &["ssh", "scp", "sftp", "mosh"];
+
+/// Returns a completion script for `shell` (`bash`, `zsh` or `fish`)
+/// that completes the host names that `command` prints, one per line.
+pub fn script(shell: &str, command: &[String]) -> Result<String> {
+    let command = shell_words(command);
+    let script = match shell {
+        "bash" => format!(
+            r#"# Host name completion from ssh_bookmarker.
+_ssh_bookmarker_hosts() {{
+    local cur="${{COMP_WORDS[COMP_CWORD]}}" user=""
+    if [[ $cur == *@* ]]; then
+        user="${{cur%%@*}}@"
+        cur="${{cur#*@}}"
+    fi
+    COMPREPLY=($(compgen -P "$user" -W "$({} 2>/dev/null)" -- "$cur"))
+}}
+complete -o default -F _ssh_bookmarker_hosts {}
+"#,
+            command,
+            COMMANDS.join(" ")
+        ),
+        "zsh" => format!(
+            "# Host name completion from ssh_bookmarker.\n\
+             zstyle -e ':completion:*:({}):*' hosts {}\n",
+            COMMANDS.join("|"),
+            shell_words(&[format!("reply=(${{(f)\"$({} 2>/dev/null)\"}})", command)])
+        ),
+        "fish" => {
+            let mut script = format!(
+                "# Host name completion from ssh_bookmarker.\n\
+                 function __ssh_bookmarker_hosts\n    {} 2>/dev/null\nend\n",
+                command
+            );
+            for client in COMMANDS {
+                script.push_str(&format!(
+                    "complete -c {} -a '(__ssh_bookmarker_hosts)'\n",
+                    client
+                ));
+            }
+            script
+        }
+        _ => bail!(ErrorKind::UnknownShell(shell.to_string())),
+    };
+    Ok(script)
+}
+
+#[test]
+fn test_scripts() {
+    let command: Vec<String> = vec![
+        "/usr/local/bin/ssh_bookmarker",
+        "hosts",
+        "--names-only",
+        "-c",
+        "/Users/me/My Config",
+    ]
+    .into_iter()
+    .map(|a| a.to_string())
+    .collect();
+    let quoted = "/usr/local/bin/ssh_bookmarker hosts --names-only -c '/Users/me/My Config'";
+
+    let bash = script("bash", &command).unwrap();
+    assert!(bash.contains(&format!("-W \"$({} 2>/dev/null)\"", quoted)));
+    assert!(bash.ends_with("complete -o default -F _ssh_bookmarker_hosts ssh scp sftp mosh\n"));
+
+    assert_eq!(
+        script("zsh", &command).unwrap(),
+        "# Host name completion from ssh_bookmarker.\n\
+         zstyle -e ':completion:*:(ssh|scp|sftp|mosh):*' hosts \
+         'reply=(${(f)\"$(/usr/local/bin/ssh_bookmarker hosts --names-only \
+         -c '\\''/Users/me/My Config'\\'' 2>/dev/null)\"})'\n"
+    );
+
+    let fish = script("fish", &command).unwrap();
+    assert!(fish.contains(&format!("\n    {} 2>/dev/null\n", quoted)));
+    assert!(fish.contains("complete -c mosh -a '(__ssh_bookmarker_hosts)'\n"));
+
+    assert!(script("tcsh", &command).is_err());
+}
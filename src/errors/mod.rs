@@ -3,18 +3,49 @@ use std::path::PathBuf;
 // Create the Error, ErrorKind, ResultExt, and Result types
 error_chain! {
     foreign_links {
+        Csv(::csv::Error);
         Format(::regex::Error);
+        Glob(::glob::PatternError);
         Io(::std::io::Error);
+        Json(::serde_json::Error);
+        Yaml(::serde_yaml::Error);
     }
     errors {
+        BookmarkPath(name: String) {
+            display("{:?} is not a file name inside the output directory", name)
+        }
+        ClientFormat(spec: String) {
+            display("{} is not a valid client spec: format is PROTOCOL=COMMAND", spec)
+        }
         ConditionFormat(spec: String) {
-            display("{} is not a valid condition spec: format is FILENAME,REGEX", spec)
+            display("{} is not a valid condition spec: format is FILENAME,REGEX or FILENAME,glob:|suffix:|cidr:PATTERN", spec)
+        }
+        ConfigFormat(path: PathBuf, lineno: usize, line: String) {
+            display("{} line {}: {:?}", path.to_str().unwrap_or("(unprintable path)"), lineno, line)
         }
         KnownHostFormat(path: PathBuf, lineno: usize, line: String) {
             display("{} line {}: {:?}", path.to_str().unwrap_or("(unprintable path)"), lineno, line)
         }
+        KnownHostMarker(path: PathBuf, lineno: usize, marker: String) {
+            display("{} line {}: unknown marker {:?}", path.to_str().unwrap_or("(unprintable path)"), lineno, marker)
+        }
         NameError(name: String, protocol: String) {
             display("{} with protocol {} would result in a bad filename", name, protocol)
         }
+        NoClient(protocol: String) {
+            display("don't know how to connect with {}: pass --client {}=COMMAND", protocol, protocol)
+        }
+        RangeFormat(range: String) {
+            display("{:?} is not a valid CIDR range like 10.0.0.0/8", range)
+        }
+        UnknownFormat(name: String) {
+            display("{:?} is not a known output format", name)
+        }
+        UnknownGrouping(name: String) {
+            display("{:?} is not a known way to group bookmarks", name)
+        }
+        UnknownShell(name: String) {
+            display("{:?} is not a shell that completions can be made for", name)
+        }
     }
 }
@@ -0,0 +1,107 @@
+use errors::*;
+use std::io::prelude::*;
+use {Conditions, Host};
+
+/// Writes where each of `hosts` was found, the conditions that were
+/// checked for it, and what decided whether it gets a bookmark.
+pub fn write<W: Write>(hosts: &[&Host], conds: &Conditions, mut out: W) -> Result<()> {
+    for host in hosts {
+        write!(
+            out,
+            "{} ({}) from {}",
+            host.name,
+            host.protocol,
+            host.from.display()
+        )?;
+        if let Some(line) = host.line {
+            write!(out, " line {}", line)?;
+        }
+        writeln!(out)?;
+        if host.is_wildcard() {
+            writeln!(out, "  not bookmarked: it's a wildcard pattern")?;
+            continue;
+        }
+
+        let verdict = conds.verdict(host);
+        for check in verdict.checked.iter() {
+            let result = if check.matched {
+                "matches"
+            } else {
+                "doesn't match"
+            };
+            writeln!(out, "  {}: {}", check, result)?;
+        }
+        let outcome = if verdict.eligible {
+            "bookmarked"
+        } else {
+            "not bookmarked"
+        };
+        match verdict.decided_by() {
+            Some(check) => writeln!(out, "  {}: decided by {}", outcome, check)?,
+            None if verdict.address => writeln!(
+                out,
+                "  {}: it's an IP address ({}), and those need --keep-addresses",
+                outcome,
+                host.kind()
+            )?,
+            None if verdict.checked.is_empty() => {
+                writeln!(out, "  {}: no conditions apply", outcome)?
+            }
+            None if verdict.eligible => writeln!(out, "  {}: no condition matches", outcome)?,
+            None => writeln!(
+                out,
+                "  {}: no condition matches, and there are include conditions",
+                outcome
+            )?,
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_explain() {
+    use std::path::Path;
+    use Condition;
+
+    let from = Path::new("/example/ssh_config");
+    let mut web = Host::named("web.example.com", from);
+    web.line = Some(4);
+    let known = Host::new("web.example.com", "mosh", Path::new("/example/known_hosts"));
+    let wildcard = Host::named("*.example.com", from);
+    let mut conds = Conditions::default();
+    let (path, cond) = Condition::exclude_from("/example/ssh_config,^db").unwrap();
+    conds.add(path, cond);
+    let (path, cond) = Condition::include_from("/example/ssh_config,glob:web.*").unwrap();
+    conds.add(path, cond);
+    let (path, cond) = Condition::include_from("*,suffix:internal").unwrap();
+    conds.add(path, cond);
+
+    let mut out = vec![];
+    write(&[&web, &known, &wildcard], &conds, &mut out).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "web.example.com (ssh) from /example/ssh_config line 4\n  \
+         exclude ^db (for /example/ssh_config): doesn't match\n  \
+         include glob:web.* (for /example/ssh_config): matches\n  \
+         bookmarked: decided by include glob:web.* (for /example/ssh_config)\n\
+         web.example.com (mosh) from /example/known_hosts\n  \
+         include suffix:internal (for any file): doesn't match\n  \
+         not bookmarked: no condition matches, and there are include conditions\n\
+         *.example.com (ssh) from /example/ssh_config\n  \
+         not bookmarked: it's a wildcard pattern\n"
+    );
+
+    let mut out = vec![];
+    write(&[&known], &Conditions::default(), &mut out).unwrap();
+    assert!(String::from_utf8(out)
+        .unwrap()
+        .ends_with("\n  bookmarked: no conditions apply\n"));
+
+    let mut out = vec![];
+    let mut address = Host::named("fe80::1%en0", Path::new("/example/known_hosts"));
+    address.known_host = true;
+    write(&[&address], &Conditions::default(), &mut out).unwrap();
+    assert!(String::from_utf8(out).unwrap().ends_with(
+        "\n  not bookmarked: it's an IP address (link-local), and those need --keep-addresses\n"
+    ));
+}
@@ -0,0 +1,111 @@
+use errors::*;
+use std::io::prelude::*;
+use std::path::Path;
+use {Conditions, Host};
+
+/// A host as `list` reports it.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Record<'a> {
+    pub name: &'a str,
+    pub protocol: &'a str,
+    pub source: &'a Path,
+    pub line: Option<usize>,
+    pub hostname: Option<&'a str>,
+    pub port: Option<u16>,
+    pub user: Option<&'a str>,
+    /// Whether the host gets no bookmark, because it's a wildcard or
+    /// because of the include/exclude conditions.
+    pub filtered: bool,
+}
+
+impl<'a> Record<'a> {
+    pub fn new(host: &'a Host, conds: &Conditions) -> Record<'a> {
+        Record {
+            name: &host.name,
+            protocol: &host.protocol,
+            source: &host.from,
+            line: host.line,
+            hostname: host.hostname.as_deref(),
+            port: host.port,
+            user: host.user.as_deref(),
+            filtered: host.ineligible(conds),
+        }
+    }
+}
+
+/// Writes `records` to `out` in the format called `format`: `json`,
+/// `csv` or `yaml`.
+pub fn write<W: Write>(format: &str, records: &[Record], mut out: W) -> Result<()> {
+    match format {
+        "json" => {
+            ::serde_json::to_writer_pretty(&mut out, records)?;
+            writeln!(out)?;
+        }
+        "csv" => {
+            let mut writer = ::csv::Writer::from_writer(out);
+            for record in records {
+                writer.serialize(record)?;
+            }
+            writer.flush()?;
+        }
+        "yaml" => {
+            ::serde_yaml::to_writer(&mut out, records)?;
+            writeln!(out)?;
+        }
+        _ => bail!(ErrorKind::UnknownFormat(format.to_string())),
+    }
+    Ok(())
+}
+
+#[test]
+fn test_records() {
+    use Condition;
+
+    let from = Path::new("/etc/ssh/ssh_config");
+    let mut web = Host::named("web", from);
+    web.line = Some(3);
+    web.user = Some("deploy".to_string());
+    web.port = Some(2222);
+    let wildcard = Host::new("*.example.com", "mosh", from);
+    let mut conds = Conditions::default();
+    let (path, cond) = Condition::exclude_from("/etc/ssh/ssh_config,^db").unwrap();
+    conds.add(path, cond);
+    let db = Host::named("db", from);
+    let hosts = [web, wildcard, db];
+    let records: Vec<Record> = hosts.iter().map(|h| Record::new(h, &conds)).collect();
+
+    let mut csv = vec![];
+    write("csv", &records, &mut csv).unwrap();
+    assert_eq!(
+        String::from_utf8(csv).unwrap(),
+        "name,protocol,source,line,hostname,port,user,filtered\n\
+         web,ssh,/etc/ssh/ssh_config,3,,2222,deploy,false\n\
+         *.example.com,mosh,/etc/ssh/ssh_config,,,,,true\n\
+         db,ssh,/etc/ssh/ssh_config,,,,,true\n"
+    );
+
+    let mut json = vec![];
+    write("json", &records[..1], &mut json).unwrap();
+    let parsed: ::serde_json::Value = ::serde_json::from_slice(&json).unwrap();
+    assert_eq!(
+        parsed,
+        json!([{
+            "name": "web",
+            "protocol": "ssh",
+            "source": "/etc/ssh/ssh_config",
+            "line": 3,
+            "hostname": null,
+            "port": 2222,
+            "user": "deploy",
+            "filtered": false,
+        }])
+    );
+
+    let mut yaml = vec![];
+    write("yaml", &records[1..2], &mut yaml).unwrap();
+    assert!(String::from_utf8(yaml)
+        .unwrap()
+        .contains("- name: \"*.example.com\"\n  protocol: mosh\n"));
+
+    assert!(write("xml", &records, vec![]).is_err());
+}
@@ -1,15 +1,170 @@
+use base64;
 use errors::*;
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use ssh_config::pattern_matches;
+use std::fs::File;
 use std::io::prelude::*;
+use std::io::BufReader;
 use std::path::{Path, PathBuf};
-use {ConfigFile, Host};
+use {ConfigFile, Host, HostKind};
 
 pub struct KnownHosts {
     pathname: PathBuf,
+    candidates: Vec<String>,
+    include_revoked: bool,
+    prefer_names: bool,
 }
 
 impl From<PathBuf> for KnownHosts {
     fn from(path: PathBuf) -> KnownHosts {
-        KnownHosts { pathname: path }
+        KnownHosts {
+            pathname: path,
+            candidates: vec![],
+            include_revoked: false,
+            prefer_names: false,
+        }
+    }
+}
+
+/// The marker that a known_hosts line can start with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Marker {
+    /// `@cert-authority`: the key is a CA that signs the host keys of
+    /// the hosts matching the line's patterns.
+    CertAuthority,
+    /// `@revoked`: the key must never be accepted for the line's hosts.
+    Revoked,
+}
+
+/// A non-empty line of a known_hosts file.
+#[derive(Debug, PartialEq)]
+pub struct Entry<'a> {
+    pub marker: Option<Marker>,
+    /// The host names, patterns or hashes that the line's key is for.
+    pub hosts: Vec<&'a str>,
+}
+
+/// Splits a known_hosts line into its marker and hosts. Returns `None`
+/// for blank lines and comments. `lineno` counts from 1.
+pub fn parse_entry<'a>(pathname: &Path, lineno: usize, line: &'a str) -> Result<Option<Entry<'a>>> {
+    let line = line.trim();
+    // Skip comments or blank lines:
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+
+    let format_error =
+        || ErrorKind::KnownHostFormat(pathname.to_path_buf(), lineno, line.to_string());
+    let mut items = line.split_whitespace();
+    let mut host_item = items.next().ok_or_else(format_error)?;
+    let mut marker = None;
+    if host_item.starts_with('@') {
+        marker = Some(match host_item {
+            "@cert-authority" => Marker::CertAuthority,
+            "@revoked" => Marker::Revoked,
+            _ => bail!(ErrorKind::KnownHostMarker(
+                pathname.to_path_buf(),
+                lineno,
+                host_item.to_string()
+            )),
+        });
+        // the hosts list is the next item if the first is a marker
+        host_item = items.next().ok_or_else(format_error)?;
+    }
+    Ok(Some(Entry {
+        marker,
+        hosts: host_item.split(',').collect(),
+    }))
+}
+
+impl KnownHosts {
+    /// Recover the names in hashed entries (as written with
+    /// `HashKnownHosts yes`) by hashing each of `candidates` and
+    /// checking for a match. Candidates for non-default ports need to
+    /// be given in the `[host]:port` form that ssh hashes.
+    pub fn with_candidates(mut self, candidates: &[String]) -> KnownHosts {
+        self.candidates = candidates.to_vec();
+        self
+    }
+
+    /// Also bookmark the hosts on `@revoked` lines, which are skipped
+    /// by default.
+    pub fn with_revoked(mut self, include_revoked: bool) -> KnownHosts {
+        self.include_revoked = include_revoked;
+        self
+    }
+
+    /// On lines that list a host by name and by IP address, only keep
+    /// the names.
+    pub fn with_preferred_names(mut self, prefer_names: bool) -> KnownHosts {
+        self.prefer_names = prefer_names;
+        self
+    }
+
+    /// Returns the host patterns of the file's `@cert-authority` lines.
+    pub fn cert_authority_patterns(&self) -> Result<Vec<String>> {
+        let f = File::open(self.pathname())?;
+        let mut patterns = vec![];
+        for (lineno, maybe_line) in BufReader::new(f).lines().enumerate() {
+            let line = maybe_line?;
+            if let Some(entry) = parse_entry(self.pathname(), lineno + 1, &line)? {
+                if entry.marker == Some(Marker::CertAuthority) {
+                    patterns.extend(entry.hosts.iter().map(|p| p.to_string()));
+                }
+            }
+        }
+        Ok(patterns)
+    }
+
+    fn process_entry(&self, lineno: usize, line: &str) -> Result<Vec<Host>> {
+        let pathname = self.pathname();
+        let entry = match parse_entry(pathname, lineno, line)? {
+            Some(entry) => entry,
+            None => return Ok(vec![]),
+        };
+        match entry.marker {
+            // CA lines are for whole groups of hosts, not any one host:
+            Some(Marker::CertAuthority) => return Ok(vec![]),
+            Some(Marker::Revoked) if !self.include_revoked => return Ok(vec![]),
+            _ => {}
+        }
+
+        let format_error =
+            || ErrorKind::KnownHostFormat(pathname.to_path_buf(), lineno, line.to_string());
+        let mut hosts: Vec<Host> = vec![];
+        for item in entry.hosts {
+            if item.starts_with('|') {
+                // hashed hosts can only be recovered by guessing their names:
+                if self.candidates.is_empty() {
+                    continue;
+                }
+                let (salt, hash) = parse_hashed(item).ok_or_else(format_error)?;
+                for candidate in self.candidates.iter() {
+                    if hash_host(&salt, candidate) != hash {
+                        continue;
+                    }
+                    if let Some((name, port)) = split_port(candidate) {
+                        let mut host = Host::named(name, pathname);
+                        host.port = port;
+                        host.line = Some(lineno);
+                        host.known_host = true;
+                        hosts.push(host);
+                    }
+                }
+                continue;
+            }
+            let (name, port) = split_port(item).ok_or_else(format_error)?;
+            let mut host = Host::named(name, pathname);
+            host.port = port;
+            host.line = Some(lineno);
+            host.known_host = true;
+            hosts.push(host);
+        }
+        if self.prefer_names && hosts.iter().any(|h| h.kind() == HostKind::Name) {
+            hosts.retain(|h| h.kind() == HostKind::Name);
+        }
+        Ok(hosts)
     }
 }
 
@@ -19,81 +174,527 @@ impl ConfigFile for KnownHosts {
     }
 
     fn parse_entries<R: BufRead>(&self, file: R) -> Result<Vec<Host>> {
+        let lines = file.lines().collect::<::std::io::Result<Vec<String>>>()?;
         let mut hosts: Vec<Host> = vec![];
-        for (lineno, maybe_line) in file.lines().enumerate() {
-            let line = try!(maybe_line);
-            hosts.extend(try!(process_entry(self.pathname(), lineno, &line)));
+        for (lineno, line) in lines.iter().enumerate() {
+            hosts.extend(self.process_entry(lineno + 1, line)?);
+        }
+        if !self.include_revoked {
+            // A host whose key was revoked anywhere in the file shouldn't
+            // get a bookmark from its other lines either:
+            let revoked = revoked_patterns(self.pathname(), &lines);
+            hosts.retain(|host| !is_revoked(host, &revoked));
         }
         Ok(hosts)
     }
 }
 
-fn process_entry(pathname: &Path, lineno: usize, line: &str) -> Result<Vec<Host>> {
-    let mut hosts: Vec<Host> = vec![];
-    let line = line.trim();
-    // Skip comments or blank lines:
-    if line.is_empty() || line.starts_with('#') {
-        return Ok(vec![]);
+/// Returns the host patterns of the `@revoked` lines in `lines`.
+/// Malformed lines are left to `process_entry` to report.
+fn revoked_patterns(pathname: &Path, lines: &[String]) -> Vec<String> {
+    let mut patterns = vec![];
+    for (lineno, line) in lines.iter().enumerate() {
+        if let Ok(Some(entry)) = parse_entry(pathname, lineno + 1, line) {
+            if entry.marker == Some(Marker::Revoked) {
+                patterns.extend(entry.hosts.iter().map(|p| p.to_string()));
+            }
+        }
     }
+    patterns
+}
 
-    let mut items = line.split_whitespace();
-    let mut host_item = try!(items.next().ok_or_else(|| ErrorKind::KnownHostFormat(
-        pathname.to_path_buf(),
-        lineno,
-        line.to_string()
-    )));
-    if host_item.starts_with('@') {
-        // the hosts list is the next item if the first is a marker
-        host_item = try!(items.next().ok_or_else(|| ErrorKind::KnownHostFormat(
-            pathname.to_path_buf(),
-            lineno,
-            line.to_string()
-        )));
+/// Whether `host` matches any of the `revoked` patterns, which can be
+/// hashed, or wildcards like those of ssh_config's `Host` lines.
+fn is_revoked(host: &Host, revoked: &[String]) -> bool {
+    let name = match host.port {
+        Some(port) => format!("[{}]:{}", host.name, port),
+        None => host.name.clone(),
+    };
+    revoked.iter().any(|pattern| {
+        if pattern.starts_with('|') {
+            parse_hashed(pattern).is_some_and(|(salt, hash)| hash_host(&salt, &name) == hash)
+        } else {
+            !pattern.starts_with('!') && pattern_matches(pattern, &name)
+        }
+    })
+}
+
+/// Splits a hashed known_hosts entry, `|1|salt|hash`, into the
+/// decoded salt and hash.
+fn parse_hashed(item: &str) -> Option<(Vec<u8>, Vec<u8>)> {
+    let mut parts = item.split('|');
+    match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(""), Some("1"), Some(salt), Some(hash)) if parts.next().is_none() => {
+            Some((base64::decode(salt).ok()?, base64::decode(hash).ok()?))
+        }
+        _ => None,
+    }
+}
+
+/// Hashes a host name the way ssh does for `HashKnownHosts`: the
+/// HMAC-SHA1 of the name, keyed with the entry's salt.
+fn hash_host(salt: &[u8], name: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha1>::new_varkey(salt).expect("HMAC takes keys of any length");
+    mac.input(name.as_bytes());
+    mac.result().code().to_vec()
+}
+
+/// Returns the names that ssh could have hashed when connecting to
+/// the hosts from an ssh_config file: their aliases and `HostName`s,
+/// in the `[host]:port` form if they have a `Port`.
+pub fn config_candidates(hosts: &[Host]) -> Vec<String> {
+    let mut candidates = vec![];
+    for host in hosts {
+        for name in Some(&host.name).into_iter().chain(host.hostname.as_ref()) {
+            match host.port {
+                Some(port) => candidates.push(format!("[{}]:{}", name, port)),
+                None => candidates.push(name.to_string()),
+            }
+        }
+    }
+    candidates
+}
+
+/// Returns the domains of `@cert-authority` patterns like
+/// `*.corp.example`, which hint at the domains that unqualified host
+/// names live in.
+pub fn cert_authority_domains(patterns: &[String]) -> Vec<String> {
+    patterns
+        .iter()
+        .filter_map(|p| p.strip_prefix("*."))
+        .filter(|domain| !domain.contains(&['*', '?', '!'][..]))
+        .map(|domain| domain.to_string())
+        .collect()
+}
+
+/// Adds each of `domains` to the candidates that aren't fully
+/// qualified, so `db1` can also be tried as `db1.corp.example`.
+pub fn qualify_candidates(candidates: &[String], domains: &[String]) -> Vec<String> {
+    let mut qualified = candidates.to_vec();
+    for candidate in candidates.iter() {
+        if candidate.contains(&['.', ':', '['][..]) {
+            continue;
+        }
+        for domain in domains.iter() {
+            qualified.push(format!("{}.{}", candidate, domain));
+        }
     }
-    if host_item.starts_with('|') {
-        // hashed hosts can't be processed meaningfully, so don't do anything:
-        return Ok(vec![]);
+    qualified
+}
+
+/// Reads a wordlist with one candidate host name per line. Blank lines
+/// and lines starting with `#` are ignored.
+pub fn wordlist_candidates<R: BufRead>(file: R) -> Result<Vec<String>> {
+    let mut candidates = vec![];
+    for maybe_line in file.lines() {
+        let line = maybe_line?;
+        let line = line.trim();
+        if !line.is_empty() && !line.starts_with('#') {
+            candidates.push(line.to_string());
+        }
     }
-    for host in host_item.split(',') {
-        hosts.push(Host::named(host, pathname));
+    Ok(candidates)
+}
+
+/// Returns the options of `client` that take an argument, and the
+/// one of those that sets the ssh port, if there is one.
+fn client_options(client: &str) -> (&'static str, Option<char>) {
+    match client {
+        "ssh" => ("BbcDEeFIiJLlmOoPpQRSWw", Some('p')),
+        "scp" => ("cDFiJloPSX", Some('P')),
+        "sftp" => ("BbcDFiJloPRSsX", Some('P')),
+        // mosh's `-p` is the port of its own UDP connection:
+        _ => ("p", None),
     }
-    Ok(hosts)
+}
+
+/// Collects the destinations of `ssh`, `mosh`, `scp` and `sftp`
+/// commands from a bash or zsh history file.
+pub fn history_candidates<R: BufRead>(mut file: R) -> Result<Vec<String>> {
+    let mut candidates = vec![];
+    let mut buf = vec![];
+    while file.read_until(b'\n', &mut buf)? > 0 {
+        {
+            let line = String::from_utf8_lossy(&buf);
+            let mut line = line.trim();
+            // zsh's extended history format: `: <timestamp>:<duration>;<command>`
+            if line.starts_with(": ") {
+                line = line.split_once(';').map_or("", |(_, command)| command);
+            }
+            for command in line.split(&[';', '|', '&'][..]) {
+                candidates.extend(command_destinations(command));
+            }
+        }
+        buf.clear();
+    }
+    Ok(candidates)
+}
+
+fn command_destinations(command: &str) -> Vec<String> {
+    let mut words = command.split_whitespace();
+    let client = loop {
+        match words.next() {
+            Some(word) => {
+                let program = word.rsplit('/').next().unwrap_or(word);
+                if ["ssh", "mosh", "scp", "sftp"].contains(&program) {
+                    break program;
+                }
+            }
+            None => return vec![],
+        }
+    };
+
+    let (with_argument, port_option) = client_options(client);
+    let mut port: Option<u16> = None;
+    let mut destinations = vec![];
+    while let Some(word) = words.next() {
+        if let Some(option) = word.strip_prefix('-') {
+            let mut chars = option.chars();
+            match (chars.next(), chars.as_str()) {
+                (Some(c), "") if Some(c) == port_option => {
+                    port = words.next().and_then(|p| p.parse().ok());
+                }
+                (Some(c), "") if with_argument.contains(c) => {
+                    words.next();
+                }
+                (Some(c), value) if Some(c) == port_option => port = value.parse().ok(),
+                _ => {}
+            }
+            continue;
+        }
+        if client == "scp" {
+            // scp has any number of arguments, remote ones are `host:path`:
+            if let Some(colon) = word.find(':') {
+                push_destination(&mut destinations, &word[..colon], port);
+            }
+            continue;
+        }
+        match word.strip_prefix("ssh://") {
+            Some(url) => {
+                let authority = url.split('/').next().unwrap_or(url);
+                let hostport = authority.rsplit('@').next().unwrap_or(authority);
+                let (host, url_port) = match hostport.rfind(':') {
+                    Some(colon)
+                        if !hostport.ends_with(']')
+                            && (hostport.starts_with('[') || !hostport[..colon].contains(':')) =>
+                    {
+                        (&hostport[..colon], hostport[colon + 1..].parse().ok())
+                    }
+                    _ => (hostport, None),
+                };
+                let host = host.trim_start_matches('[').trim_end_matches(']');
+                push_destination(&mut destinations, host, url_port.or(port));
+            }
+            None if client == "sftp" => push_destination(
+                &mut destinations,
+                word.split(':').next().unwrap_or(word),
+                port,
+            ),
+            None => push_destination(&mut destinations, word, port),
+        }
+        // Whatever comes after the destination is the remote command.
+        break;
+    }
+    destinations
+}
+
+/// Adds the host of `destination` to `destinations`, and if it was
+/// connected to on another port, also the `[host]:port` form that ssh
+/// hashes for that.
+fn push_destination(destinations: &mut Vec<String>, destination: &str, port: Option<u16>) {
+    if let Some(host) = destination_host(destination) {
+        if let Some(port) = port {
+            destinations.push(format!("[{}]:{}", host, port));
+        }
+        destinations.push(host);
+    }
+}
+
+/// Strips the user from a `user@host` destination, and returns the
+/// host if it looks like a host name.
+fn destination_host(destination: &str) -> Option<String> {
+    let host = destination.rsplit('@').next().unwrap_or(destination);
+    if host.is_empty() || host.starts_with('-') || host.contains(&['/', '$', '`', '"', '\''][..]) {
+        return None;
+    }
+    Some(host.to_string())
+}
+
+/// Splits a known_hosts entry for a non-default port, `[host]:port`,
+/// into the host and the port. Entries without brackets are returned
+/// as they are; `None` means the brackets are malformed.
+fn split_port(item: &str) -> Option<(&str, Option<u16>)> {
+    if !item.starts_with('[') {
+        return Some((item, None));
+    }
+    let close = item.find(']')?;
+    let name = &item[1..close];
+    let port = item[close + 1..].strip_prefix(':')?.parse().ok()?;
+    if name.is_empty() {
+        return None;
+    }
+    Some((name, Some(port)))
 }
 
 #[test]
 fn test_known_hosts_entry() {
     let from = Path::new("/dev/null");
+    let kh = KnownHosts::from(from.to_path_buf());
     let no_hosts: Vec<Host> = vec![];
-    let comment: Vec<Host> = process_entry(from, 0, "# Comments allowed at start of line").unwrap();
+    let comment: Vec<Host> = kh
+        .process_entry(1, "# Comments allowed at start of line")
+        .unwrap();
     assert_eq!(no_hosts, comment);
 
-    let empty: Vec<Host> = process_entry(Path::new("/dev/null"), 0, "    ").unwrap();
+    let empty: Vec<Host> = kh.process_entry(1, "    ").unwrap();
     assert_eq!(no_hosts, empty);
 
-    let multiple: Vec<Host> = process_entry(
-        from,
-        0,
-        "closenet,closenet.example.net,192.0.2.53 1024 37 159...93 closenet.example.net ",
-    )
-    .unwrap();
+    let multiple: Vec<Host> = kh
+        .process_entry(
+            1,
+            "closenet,closenet.example.net,192.0.2.53 1024 37 159...93 closenet.example.net ",
+        )
+        .unwrap();
     let expected_multiple: Vec<Host> = vec![
         Host::named("closenet", from),
         Host::named("closenet.example.net", from),
         Host::named("192.0.2.53", from),
     ];
     assert_eq!(multiple, expected_multiple);
+    assert_eq!(multiple[0].line, Some(1));
+    assert!(multiple[2].known_host);
+    let names = KnownHosts::from(from.to_path_buf()).with_preferred_names(true);
+    assert_eq!(
+        names
+            .process_entry(1, "closenet,192.0.2.53 ssh-rsa AAAAB5W...")
+            .unwrap(),
+        vec![Host::named("closenet", from)]
+    );
+    assert_eq!(
+        names
+            .process_entry(1, "192.0.2.53 ssh-rsa AAAAB5W...")
+            .unwrap(),
+        vec![Host::named("192.0.2.53", from)]
+    );
 
-    let annotated: Vec<Host> =
-        process_entry(from, 0, "@revoked something ssh-rsa AAAAB5W...").unwrap();
-    let expected_annotated: Vec<Host> = vec![Host::named("something", from)];
-    assert_eq!(annotated, expected_annotated);
+    let revoked = "@revoked something ssh-rsa AAAAB5W...";
+    assert_eq!(no_hosts, kh.process_entry(1, revoked).unwrap());
+    let with_revoked = KnownHosts::from(from.to_path_buf()).with_revoked(true);
+    assert_eq!(
+        with_revoked.process_entry(1, revoked).unwrap(),
+        vec![Host::named("something", from)]
+    );
 
-    let hashed: Vec<Host> = process_entry(
-        from,
-        0,
-        "|1|JfKTdBh7rNbXkVAQCRp4OQoPfmI=|USECr3SWf1JUPsms5AqfD5QfxkM= ssh-rsa AAAAB5W...",
-    )
-    .unwrap();
+    let authority = "@cert-authority *.corp.example,bastion ssh-rsa AAAAB5W...";
+    assert_eq!(no_hosts, kh.process_entry(1, authority).unwrap());
+
+    match *kh
+        .process_entry(7, "@trusted something ssh-rsa AAAAB5W...")
+        .unwrap_err()
+        .kind()
+    {
+        ErrorKind::KnownHostMarker(_, lineno, ref marker) => {
+            assert_eq!(lineno, 7);
+            assert_eq!(marker, "@trusted");
+        }
+        ref other => panic!("unexpected error {:?}", other),
+    }
+    match *kh
+        .parse_entries("good ssh-rsa AAAAB5W...\n@trusted bad ssh-rsa AAAAB5W...\n".as_bytes())
+        .unwrap_err()
+        .kind()
+    {
+        ErrorKind::KnownHostMarker(_, lineno, _) => assert_eq!(lineno, 2),
+        ref other => panic!("unexpected error {:?}", other),
+    }
+
+    let ported: Vec<Host> = kh
+        .process_entry(
+            1,
+            "[gitlab.internal]:2222,[fe80::1%en0]:22,plain ssh-ed25519 AAAAC3...",
+        )
+        .unwrap();
+    let urls: Vec<String> = ported.iter().map(|h| h.url()).collect();
+    assert_eq!(
+        urls,
+        vec![
+            "ssh://gitlab.internal:2222",
+            "ssh://[fe80::1%25en0]:22",
+            "ssh://plain"
+        ]
+    );
+    assert_eq!(ported[0].name, "gitlab.internal");
+    assert_eq!(ported[1].name, "fe80::1%en0");
+
+    for malformed in &[
+        "[gitlab.internal:2222 ssh-rsa AAAAB5W...",
+        "[gitlab.internal] ssh-rsa AAAAB5W...",
+        "[gitlab.internal]:ssh ssh-rsa AAAAB5W...",
+        "[]:22 ssh-rsa AAAAB5W...",
+    ] {
+        match *kh.process_entry(3, malformed).unwrap_err().kind() {
+            ErrorKind::KnownHostFormat(_, lineno, _) => assert_eq!(lineno, 3),
+            ref other => panic!("unexpected error {:?}", other),
+        }
+    }
+
+    let hashed: Vec<Host> = kh
+        .process_entry(
+            1,
+            "|1|JfKTdBh7rNbXkVAQCRp4OQoPfmI=|USECr3SWf1JUPsms5AqfD5QfxkM= ssh-rsa AAAAB5W...",
+        )
+        .unwrap();
     let expected_hashed: Vec<Host> = vec![];
     assert_eq!(hashed, expected_hashed);
 }
+
+#[test]
+fn test_hashed_entries() {
+    let from = Path::new("/dev/null");
+    let candidates: Vec<String> = vec![
+        "closenet".to_string(),
+        "closenet.example.net".to_string(),
+        "[gitlab.internal]:2222".to_string(),
+    ];
+    let kh = KnownHosts::from(from.to_path_buf()).with_candidates(&candidates);
+
+    let plain = kh
+        .process_entry(
+            1,
+            "|1|AAECAwQFBgcICQoLDA0ODxAREhM=|DPdjy+U6hKJ6yGk6ojddi7JPDSk= ssh-rsa AAAAB5W...",
+        )
+        .unwrap();
+    assert_eq!(plain, vec![Host::named("closenet.example.net", from)]);
+
+    let ported = kh
+        .process_entry(
+            1,
+            "|1|AAECAwQFBgcICQoLDA0ODxAREhM=|R4J6Iw8gMTJI5bn1hDfGA31Sf4M= ssh-rsa AAAAB5W...",
+        )
+        .unwrap();
+    assert_eq!(ported.len(), 1);
+    assert_eq!(ported[0].url(), "ssh://gitlab.internal:2222");
+
+    let hashed_line =
+        "|1|JfKTdBh7rNbXkVAQCRp4OQoPfmI=|USECr3SWf1JUPsms5AqfD5QfxkM= ssh-rsa AAAAB5W...";
+    let recovered = kh.process_entry(1, hashed_line).unwrap();
+    assert_eq!(recovered, vec![Host::named("closenet", from)]);
+    let unknown = KnownHosts::from(from.to_path_buf())
+        .with_candidates(&candidates[1..])
+        .process_entry(1, hashed_line)
+        .unwrap();
+    assert_eq!(unknown, vec![]);
+
+    assert!(kh
+        .process_entry(1, "|1|not base64|!! ssh-rsa AAAAB5W...")
+        .is_err());
+}
+
+#[test]
+fn test_candidates() {
+    let from = Path::new("/dev/null");
+    let mut bastion = Host::named("bastion", from);
+    bastion.hostname = Some("bastion.example.com".to_string());
+    bastion.port = Some(2222);
+    assert_eq!(
+        config_candidates(&[Host::named("web", from), bastion]),
+        vec!["web", "[bastion]:2222", "[bastion.example.com]:2222"]
+    );
+
+    assert_eq!(
+        wordlist_candidates("# hosts\none\n\n  two  \n".as_bytes()).unwrap(),
+        vec!["one", "two"]
+    );
+
+    let history = "ssh web1\n\
+                   : 1561234567:0;ssh -p 2222 -i ~/.ssh/id deploy@bastion uptime\n\
+                   cd /tmp && scp notes.txt me@files:docs/ other:\n\
+                   /usr/bin/mosh --no-init db1 -- tmux attach\n\
+                   sftp backup:/srv\n\
+                   ssh ssh://root@[::1]:22 ; ssh ssh://root@vm:2200/\n\
+                   scp -P 2200 -r build/ vm:/srv/ ; sftp -oPort=1 -P2201 vm\n\
+                   ssh -v\n\
+                   scp -p files:/x . ; mosh -p 60001 db2\n\
+                   git push\n";
+    assert_eq!(
+        history_candidates(history.as_bytes()).unwrap(),
+        vec![
+            "web1",
+            "[bastion]:2222",
+            "bastion",
+            "files",
+            "other",
+            "db1",
+            "backup",
+            "[::1]:22",
+            "::1",
+            "[vm]:2200",
+            "vm",
+            "[vm]:2200",
+            "vm",
+            "[vm]:2201",
+            "vm",
+            "files",
+            "db2",
+        ]
+    );
+}
+
+#[test]
+fn test_revoked_hosts() {
+    let from = Path::new("/dev/null");
+    let salt = b"0123456789abcdefghij";
+    let hashed = format!(
+        "|1|{}|{}",
+        base64::encode(&salt[..]),
+        base64::encode(&hash_host(salt, "[db]:2222"))
+    );
+    let file = format!(
+        "old.example ssh-rsa AAAAB5W...\n\
+         web.example,db ssh-rsa AAAAB5W...\n\
+         [db]:2222 ssh-rsa AAAAB5W...\n\
+         legacy1.example ssh-rsa AAAAB5W...\n\
+         @revoked old.example,legacy*.example ssh-rsa AAAAB5W...\n\
+         @revoked {} ssh-rsa AAAAB5W...\n",
+        hashed
+    );
+
+    let kh = KnownHosts::from(from.to_path_buf());
+    let hosts = kh.parse_entries(file.as_bytes()).unwrap();
+    let names: Vec<&str> = hosts.iter().map(|h| h.name()).collect();
+    assert_eq!(names, vec!["web.example", "db"]);
+
+    let with_revoked = KnownHosts::from(from.to_path_buf()).with_revoked(true);
+    let hosts = with_revoked.parse_entries(file.as_bytes()).unwrap();
+    assert_eq!(hosts.len(), 7);
+}
+
+#[test]
+fn test_cert_authorities() {
+    use std::fs;
+
+    let dir = ::tempfile::tempdir().unwrap();
+    let path = dir.path().join("known_hosts");
+    fs::write(
+        &path,
+        "@cert-authority *.corp.example,*.lab.example ssh-rsa AAAAB5W...\n\
+         @revoked old.corp.example ssh-rsa AAAAB5W...\n\
+         web.corp.example ssh-rsa AAAAB5W...\n",
+    )
+    .unwrap();
+    let patterns = KnownHosts::from(path).cert_authority_patterns().unwrap();
+    assert_eq!(patterns, vec!["*.corp.example", "*.lab.example"]);
+
+    let domains = cert_authority_domains(&patterns);
+    assert_eq!(domains, vec!["corp.example", "lab.example"]);
+    let candidates = vec!["db1".to_string(), "web.example.net".to_string()];
+    assert_eq!(
+        qualify_candidates(&candidates, &domains),
+        vec![
+            "db1",
+            "web.example.net",
+            "db1.corp.example",
+            "db1.lab.example"
+        ]
+    );
+}
@@ -6,6 +6,7 @@ pub fn create(
     known_hosts: &[String],
     include: &[String],
     exclude: &[String],
+    options: &[String],
     output: &str,
 ) -> Result<String> {
     let curr_exe =
@@ -19,6 +20,7 @@ pub fn create(
         known_hosts,
         include,
         exclude,
+        options,
         output,
     ))
 }
@@ -48,6 +50,7 @@ fn create_for_exe(
     known_hosts: &[String],
     include: &[String],
     exclude: &[String],
+    options: &[String],
     output: &str,
 ) -> String {
     let configs: Vec<&str> = configs.iter().map(|s| s.as_str()).collect();
@@ -58,6 +61,7 @@ fn create_for_exe(
     let include_slice = include.as_slice();
     let exclude: Vec<&str> = exclude.iter().map(|s| s.as_str()).collect();
     let exclude_slice = exclude.as_slice();
+    let options: Vec<&str> = options.iter().map(|s| s.as_str()).collect();
 
     format!(r##"<?xml version="1.0" encoding="UTF-8"?>
 <!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
@@ -66,7 +70,7 @@ fn create_for_exe(
     <key>Label</key>
     <string>net.boinkor.ssh-bookmarker</string>
     <key>ProgramArguments</key>
-    <array><string>{}</string><string>create</string>{}{}{}{}{}</array>
+    <array><string>{}</string><string>create</string>{}{}{}{}{}{}</array>
     <key>QueueDirectories</key>
     <array/>
     <key>RunAtLoad</key>
@@ -87,6 +91,7 @@ fn create_for_exe(
             plist_stringify(command_lineify("-k", known_hosts_slice).as_slice()),
             plist_stringify(command_lineify("-I", include_slice).as_slice()),
             plist_stringify(command_lineify("-X", exclude_slice).as_slice()),
+            plist_stringify(options.as_slice()),
             plist_stringify(vec![output].as_slice()),
             plist_stringify(config_slice), plist_stringify(known_hosts_slice))
 }
@@ -116,8 +121,9 @@ fn test_create_for_exe() {
     let known_hosts = vec!["/etc/ssh/ssh_known_hosts".to_string()];
     let include = vec!["foo:bar".to_string()];
     let exclude = vec!["baz:qux".to_string()];
+    let options = vec!["--history".to_string(), "/tmp/history".to_string()];
 
-    assert_eq!(create_for_exe("program", &cfgs, &known_hosts, &include, &exclude, "/tmp/foo"),
+    assert_eq!(create_for_exe("program", &cfgs, &known_hosts, &include, &exclude, &options, "/tmp/foo"),
     r##"<?xml version="1.0" encoding="UTF-8"?>
 <!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
 <plist version="1.0">
@@ -125,7 +131,7 @@ fn test_create_for_exe() {
     <key>Label</key>
     <string>net.boinkor.ssh-bookmarker</string>
     <key>ProgramArguments</key>
-    <array><string>program</string><string>create</string><string>-c</string><string>/etc/ssh/ssh_config</string><string>-k</string><string>/etc/ssh/ssh_known_hosts</string><string>-I</string><string>foo:bar</string><string>-X</string><string>baz:qux</string><string>/tmp/foo</string></array>
+    <array><string>program</string><string>create</string><string>-c</string><string>/etc/ssh/ssh_config</string><string>-k</string><string>/etc/ssh/ssh_known_hosts</string><string>-I</string><string>foo:bar</string><string>-X</string><string>baz:qux</string><string>--history</string><string>/tmp/history</string><string>/tmp/foo</string></array>
     <key>QueueDirectories</key>
     <array/>
     <key>RunAtLoad</key>
@@ -1,114 +1,359 @@
+extern crate base64;
+extern crate csv;
+extern crate glob;
+extern crate hmac;
 extern crate regex;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+#[macro_use]
+extern crate serde_json;
+extern crate serde_yaml;
+extern crate sha1;
+#[cfg(test)]
+extern crate tempfile;
 
+pub mod bookmarks;
+pub mod completions;
 pub mod errors;
+pub mod explain;
+pub mod inventory;
 pub mod known_hosts;
 pub mod launchagent;
+pub mod matcher;
+pub mod pick;
 pub mod ssh_config;
+pub mod suggest;
+pub mod sync;
 
 #[macro_use]
 extern crate error_chain;
 
-use std::collections::HashMap;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fmt;
 use std::fs::File;
 use std::io::prelude::*;
 use std::io::BufReader;
+use std::net::IpAddr;
 use std::path::{Path, PathBuf};
 
 use errors::*;
+use matcher::Matcher;
 
-use regex::Regex;
-
+/// A condition on the hosts' names and, if `protocol` is given, on
+/// their protocol.
 pub enum Condition {
-    Include(Regex),
-    Exclude(Regex),
+    Include {
+        protocol: Option<String>,
+        matcher: Matcher,
+    },
+    Exclude {
+        protocol: Option<String>,
+        matcher: Matcher,
+    },
     Everything, // TODO: do we need this?
 }
 
+/// Splits a `FILE,MATCHER` spec, where the matcher can start with a
+/// protocol like `mosh://`. With nothing after the protocol, the
+/// condition is for all the hosts with that protocol.
+fn parse_spec(spec: &str) -> Result<(PathBuf, Option<String>, Matcher)> {
+    let (path, matcher) = spec
+        .split_once(',')
+        .ok_or_else(|| ErrorKind::ConditionFormat(spec.to_string()))?;
+    let (protocol, matcher) = match matcher.split_once("://") {
+        Some((protocol, rest)) if is_protocol(protocol) => (Some(protocol.to_string()), rest),
+        _ => (None, matcher),
+    };
+    Ok((PathBuf::from(path), protocol, Matcher::parse(matcher)?))
+}
+
+/// Whether `name` could be the scheme of a URL.
+fn is_protocol(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.')
+}
+
 impl Condition {
     pub fn exclude_from(spec: &str) -> Result<(PathBuf, Condition)> {
-        let mut split = spec.splitn(2, ',');
-        let path = PathBuf::from(
-            split
-                .next()
-                .ok_or_else(|| ErrorKind::ConditionFormat(spec.to_string()))?,
-        );
-        let cond = Condition::Exclude(
-            Regex::new(
-                split
-                    .next()
-                    .ok_or_else(|| ErrorKind::ConditionFormat(spec.to_string()))?,
-            )
-            .chain_err(|| "could not parse the host regex")?,
-        );
-        Ok((path, cond))
+        let (path, protocol, matcher) = parse_spec(spec)?;
+        Ok((path, Condition::Exclude { protocol, matcher }))
     }
 
     pub fn include_from(spec: &str) -> Result<(PathBuf, Condition)> {
-        let mut split = spec.splitn(2, ',');
-        let path = PathBuf::from(
-            split
-                .next()
-                .ok_or_else(|| ErrorKind::ConditionFormat(spec.to_string()))?,
-        );
-        let cond = Condition::Include(
-            Regex::new(
-                split
-                    .next()
-                    .ok_or_else(|| ErrorKind::ConditionFormat(spec.to_string()))?,
-            )
-            .chain_err(|| "could not parse the host regex")?,
-        );
-        Ok((path, cond))
+        let (path, protocol, matcher) = parse_spec(spec)?;
+        Ok((path, Condition::Include { protocol, matcher }))
+    }
+
+    /// Whether the condition is about hosts with `host_protocol` at all.
+    fn covers(&self, host_protocol: &str) -> bool {
+        match *self {
+            Condition::Include { ref protocol, .. } | Condition::Exclude { ref protocol, .. } => {
+                protocol.as_ref().is_none_or(|p| p == host_protocol)
+            }
+            Condition::Everything => true,
+        }
+    }
+
+    /// Whether the condition is about `host`.
+    fn matches(&self, host: &Host) -> bool {
+        match *self {
+            Condition::Include { ref matcher, .. } | Condition::Exclude { ref matcher, .. } => {
+                self.covers(&host.protocol) && matcher.is_match(&host.name)
+            }
+            Condition::Everything => true,
+        }
     }
 }
 
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (action, protocol, matcher) = match *self {
+            Condition::Include {
+                ref protocol,
+                ref matcher,
+            } => ("include", protocol, matcher),
+            Condition::Exclude {
+                ref protocol,
+                ref matcher,
+            } => ("exclude", protocol, matcher),
+            Condition::Everything => return write!(f, "include everything"),
+        };
+        match *protocol {
+            Some(ref protocol) => write!(f, "{} {}://{}", action, protocol, matcher),
+            None => write!(f, "{} {}", action, matcher),
+        }
+    }
+}
+
+/// A condition that was checked for a host.
+pub struct Check<'a> {
+    /// The file the condition is for, or `None` if it's for any file.
+    pub path: Option<&'a Path>,
+    pub condition: &'a Condition,
+    pub matched: bool,
+}
+
+impl<'a> fmt::Display for Check<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.path {
+            Some(path) => write!(f, "{} (for {})", self.condition, path.display()),
+            None => write!(f, "{} (for any file)", self.condition),
+        }
+    }
+}
+
+/// How the conditions decided about a host.
+pub struct Verdict<'a> {
+    /// The conditions that were checked, in order. Checking stops at
+    /// the first one that matches.
+    pub checked: Vec<Check<'a>>,
+    pub eligible: bool,
+    /// Whether the host was left out for being an IP address from a
+    /// known_hosts file.
+    pub address: bool,
+}
+
+impl<'a> Verdict<'a> {
+    /// Returns the condition that decided, or `None` if no condition
+    /// matched and the default applied: hosts go in unless there are
+    /// include conditions for them, or they're IP addresses.
+    pub fn decided_by(&self) -> Option<&Check<'a>> {
+        self.checked.last().filter(|check| check.matched)
+    }
+}
+
+/// The FILE part of an `-I`/`-X` spec that makes the condition apply to
+/// hosts from every file.
+pub const ANY_FILE: &str = "*";
+
+/// Returns the path that `path` names, with a leading `~/` expanded and
+/// symlinks resolved, so that different spellings of one file compare
+/// equal. Paths that don't exist are returned as they are.
+fn canonical_path(path: &Path) -> PathBuf {
+    let path = match (path.strip_prefix("~"), env::var_os("HOME")) {
+        (Ok(rest), Some(home)) => Path::new(&home).join(rest),
+        _ => path.to_path_buf(),
+    };
+    path.canonicalize().unwrap_or(path)
+}
+
 #[derive(Default)]
 pub struct Conditions {
     map: HashMap<PathBuf, Vec<Condition>>,
+    global: Vec<Condition>,
+    keep_addresses: bool,
 }
 
 impl Conditions {
+    /// Let hosts from known_hosts files that are IP addresses in when no
+    /// condition matches them. By default, those are left out, while
+    /// the addresses that ssh_config files name are kept.
+    pub fn with_addresses(mut self, keep_addresses: bool) -> Conditions {
+        self.keep_addresses = keep_addresses;
+        self
+    }
+
+    /// Adds a condition for the hosts from `path`, or for hosts from
+    /// any file if `path` is `ANY_FILE`.
     pub fn add(&mut self, path: PathBuf, cond: Condition) {
-        let v = self.map.entry(path).or_insert_with(|| vec![]);
+        if path == Path::new(ANY_FILE) {
+            self.global.push(cond);
+            return;
+        }
+        let v = self
+            .map
+            .entry(canonical_path(&path))
+            .or_insert_with(|| vec![]);
         v.push(cond);
     }
 
+    /// Returns the paths that conditions were given for, but that
+    /// aren't among the `read` files, so their conditions can never
+    /// apply.
+    pub fn unread<'a, I>(&self, read: I) -> Vec<&Path>
+    where
+        I: IntoIterator<Item = &'a Path>,
+    {
+        let read: HashSet<PathBuf> = read.into_iter().map(canonical_path).collect();
+        let mut unread: Vec<&Path> = self
+            .map
+            .keys()
+            .filter(|path| !read.contains(*path))
+            .map(|path| path.as_path())
+            .collect();
+        unread.sort();
+        unread
+    }
+
     pub fn eligible(&self, host: &Host) -> bool {
-        let mut default = true;
-        match self.map.get(&host.from) {
-            None => {
-                return true;
+        self.verdict(host).eligible
+    }
+
+    /// Checks the conditions for the file the host is from, and then the
+    /// ones for any file, in the order they were added.
+    pub fn verdict(&self, host: &Host) -> Verdict<'_> {
+        let for_file = if self.map.is_empty() {
+            None
+        } else {
+            self.map.get_key_value(&canonical_path(&host.from))
+        };
+        let conds = for_file
+            .into_iter()
+            .flat_map(|(path, conds)| conds.iter().map(move |cond| (Some(path.as_path()), cond)))
+            .chain(self.global.iter().map(|cond| (None, cond)));
+        let mut verdict = Verdict {
+            checked: vec![],
+            eligible: true,
+            address: false,
+        };
+        for (path, condition) in conds {
+            let matched = condition.matches(host);
+            verdict.checked.push(Check {
+                path,
+                condition,
+                matched,
+            });
+            let include = match *condition {
+                Condition::Exclude { .. } => false,
+                Condition::Include { .. } | Condition::Everything => true,
+            };
+            if matched {
+                verdict.eligible = include;
+                return verdict;
             }
-            Some(conds) => {
-                for cond in conds.iter() {
-                    match cond {
-                        Condition::Everything => {
-                            return true;
-                        }
-                        Condition::Include(ref pat) => {
-                            if pat.is_match(&host.name) {
-                                return true;
-                            }
-                            default = false;
-                        }
-                        Condition::Exclude(ref pat) => {
-                            if pat.is_match(&host.name) {
-                                return false;
-                            }
-                        }
-                    }
-                }
+            // An include for one protocol doesn't keep out the hosts
+            // with other protocols:
+            if include && condition.covers(&host.protocol) {
+                verdict.eligible = false;
             }
         }
-        default
+        if verdict.eligible
+            && !self.keep_addresses
+            && host.known_host
+            && host.kind() != HostKind::Name
+        {
+            verdict.eligible = false;
+            verdict.address = true;
+        }
+        verdict
+    }
+}
+
+/// What a host's name is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HostKind {
+    /// A DNS name, or anything else that isn't an IP address.
+    Name,
+    Ipv4,
+    Ipv6,
+    /// An IPv4 or IPv6 link-local address, which only means something
+    /// on one network.
+    LinkLocal,
+}
+
+impl fmt::Display for HostKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            HostKind::Name => "DNS name",
+            HostKind::Ipv4 => "IPv4",
+            HostKind::Ipv6 => "IPv6",
+            HostKind::LinkLocal => "link-local",
+        })
     }
 }
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone)]
 pub struct Host {
     name: String,
     protocol: String,
     from: PathBuf,
+    hostname: Option<String>,
+    user: Option<String>,
+    port: Option<u16>,
+    /// The `Tag` that ssh_config gives the host.
+    tag: Option<String>,
+    /// For wildcard hosts, the negated patterns that were next to the
+    /// wildcard on the `Host` line.
+    exceptions: Vec<String>,
+    /// The line of `from` (counting from 1) that the host was found on.
+    line: Option<usize>,
+    /// Whether the host was only found in known_hosts files, rather than
+    /// named in an ssh_config file.
+    known_host: bool,
+}
+
+impl Host {
+    /// Returns what tells hosts apart: their name, protocol and the
+    /// file they're from. Two hosts that only differ in their settings
+    /// are the same host, and would get the same bookmark file.
+    fn identity(&self) -> (&str, &str, &Path) {
+        (&self.name, &self.protocol, &self.from)
+    }
+}
+
+impl PartialEq for Host {
+    fn eq(&self, other: &Host) -> bool {
+        self.identity() == other.identity()
+    }
+}
+
+impl Eq for Host {}
+
+impl PartialOrd for Host {
+    fn partial_cmp(&self, other: &Host) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Host {
+    fn cmp(&self, other: &Host) -> Ordering {
+        self.identity().cmp(&other.identity())
+    }
 }
 
 impl Host {
@@ -117,6 +362,13 @@ impl Host {
             name: name.to_string(),
             protocol: protocol.to_string(),
             from: from.to_path_buf(),
+            hostname: None,
+            user: None,
+            port: None,
+            tag: None,
+            exceptions: vec![],
+            line: None,
+            known_host: false,
         }
     }
 
@@ -125,40 +377,158 @@ impl Host {
             name: name.to_string(),
             protocol: "ssh".to_string(),
             from: from.to_path_buf(),
+            hostname: None,
+            user: None,
+            port: None,
+            tag: None,
+            exceptions: vec![],
+            line: None,
+            known_host: false,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn kind(&self) -> HostKind {
+        match matcher::ip_literal(&self.name) {
+            None => HostKind::Name,
+            Some(IpAddr::V4(addr)) if addr.is_link_local() => HostKind::LinkLocal,
+            Some(IpAddr::V6(addr)) if addr.segments()[0] & 0xffc0 == 0xfe80 => HostKind::LinkLocal,
+            Some(IpAddr::V4(_)) => HostKind::Ipv4,
+            Some(IpAddr::V6(_)) => HostKind::Ipv6,
         }
     }
 
-    pub fn write_bookmark(&self, dir: &Path) -> Result<()> {
-        let name = format!("{} ({}).webloc", self.name, self.protocol);
-        let namepart = Path::new(&name);
+    /// Returns the file this host was read from.
+    pub fn from(&self) -> &Path {
+        &self.from
+    }
+
+    /// Returns the URL that a bookmark for this host opens, like
+    /// `ssh://deploy@bastion:2222`.
+    pub fn url(&self) -> String {
+        let mut url = format!("{}://", self.protocol);
+        if let Some(ref user) = self.user {
+            url.push_str(user);
+            url.push('@');
+        }
+        let hostname = self.hostname.as_ref().unwrap_or(&self.name);
+        if hostname.contains(':') {
+            // IPv6 literals need brackets to be told apart from the
+            // port, and their zone index needs escaping:
+            url.push_str(&format!("[{}]", hostname.replace('%', "%25")));
+        } else {
+            url.push_str(hostname);
+        }
+        if let Some(port) = self.port {
+            url.push_str(&format!(":{}", port));
+        }
+        url
+    }
 
-        let mut path = PathBuf::from(dir);
-        if namepart.is_absolute() {
-            bail!(ErrorKind::NameError(
-                self.name.to_string(),
-                self.protocol.to_string()
-            ));
+    /// Returns the command line that connects to the host with its
+    /// protocol's client. The host's name is passed on as it is, so
+    /// that ssh still applies its config to it, after a `--` so that a
+    /// name starting with `-` can't be taken for an option.
+    pub fn command(&self) -> Vec<String> {
+        let destination = match self.user {
+            Some(ref user) => format!("{}@{}", user, self.name),
+            None => self.name.clone(),
+        };
+        let mut command = vec![self.protocol.clone()];
+        if let Some(port) = self.port {
+            match self.protocol.as_str() {
+                "mosh" => command.push(format!("--ssh=ssh -p {}", port)),
+                "sftp" => command.extend(vec!["-P".to_string(), port.to_string()]),
+                _ => command.extend(vec!["-p".to_string(), port.to_string()]),
+            }
         }
-        path.push(namepart);
+        command.push("--".to_string());
+        command.push(destination);
+        command
+    }
 
-        let mut bookmark_text = String::new();
-        bookmark_text.push_str(self.protocol.as_str());
-        bookmark_text.push_str("://");
-        bookmark_text.push_str(self.name.as_str());
-        let bookmark = format!(r#"<?xml version="1.0" encoding="UTF-8"?>
-<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
-<plist version="1.0"><dict><key>URL</key><string>{}</string></dict></plist>
-"#,
-                               bookmark_text);
+    /// Returns whether the host's name matches any of the
+    /// ssh_config(5)-style `patterns` (and none of the negated ones).
+    pub fn matches_any(&self, patterns: &[String]) -> bool {
+        ssh_config::pattern_list_matches(patterns, &self.name)
+    }
 
-        let mut f = try!(File::create(path));
-        try!(f.write_all(bookmark.as_bytes()));
-        Ok(())
+    /// Returns whether the host's name is a pattern rather than a name
+    /// that can be connected to.
+    pub fn is_wildcard(&self) -> bool {
+        self.name.contains('*') || self.name.contains('?')
+    }
+
+    /// Fills in the connection settings that aren't set yet from the
+    /// wildcard host `pattern`.
+    fn inherit(&mut self, pattern: &Host) {
+        if self.hostname.is_none() {
+            self.hostname = pattern
+                .hostname
+                .as_ref()
+                .map(|h| ssh_config::expand_hostname(h, &self.name));
+        }
+        if self.user.is_none() {
+            self.user = pattern.user.clone();
+        }
+        if self.port.is_none() {
+            self.port = pattern.port;
+        }
+        if self.tag.is_none() {
+            self.tag = pattern.tag.clone();
+        }
     }
 
     pub fn ineligible(&self, conds: &Conditions) -> bool {
-        self.name.contains('*') || self.name.contains('?') || !conds.eligible(self)
+        self.is_wildcard() || !conds.eligible(self)
+    }
+}
+
+/// Expands the wildcard hosts (like those from a `Host *.example.com`
+/// block) into the concrete hosts that match them: each concrete host
+/// gets a bookmark for every protocol of the wildcards it matches, and
+/// inherits their connection settings, with earlier wildcards taking
+/// precedence like in ssh_config. The wildcard hosts themselves are
+/// returned as well.
+pub fn expand_wildcards(hosts: Vec<Host>) -> Vec<Host> {
+    let (patterns, mut concrete): (Vec<Host>, Vec<Host>) =
+        hosts.into_iter().partition(|h| h.is_wildcard());
+    let mut names: Vec<String> = concrete.iter().map(|h| h.name.clone()).collect();
+    names.sort();
+    names.dedup();
+
+    for pattern in patterns.iter() {
+        let mut pattern_list = vec![pattern.name.clone()];
+        pattern_list.extend(pattern.exceptions.iter().cloned());
+        for name in names.iter() {
+            if !ssh_config::pattern_list_matches(&pattern_list, name) {
+                continue;
+            }
+            let mut found = false;
+            for host in concrete
+                .iter_mut()
+                .filter(|h| h.name == *name && h.protocol == pattern.protocol)
+            {
+                host.inherit(pattern);
+                found = true;
+            }
+            if !found {
+                let mut host = Host::new(name, &pattern.protocol, &pattern.from);
+                host.line = pattern.line;
+                host.known_host = concrete
+                    .iter()
+                    .filter(|h| h.name == *name)
+                    .all(|h| h.known_host);
+                host.inherit(pattern);
+                concrete.push(host);
+            }
+        }
     }
+    concrete.extend(patterns);
+    concrete
 }
 
 pub trait ConfigFile {
@@ -173,24 +543,40 @@ pub trait ConfigFile {
     }
 }
 
-pub fn process<T>(pathnames: Vec<String>) -> Result<Vec<Host>>
+/// Reads the hosts from the files at `pathnames`. Files that can't be
+/// read are skipped with a warning; the paths of the others come back
+/// along with the hosts.
+pub fn process<T>(pathnames: Vec<String>) -> Result<(Vec<Host>, Vec<PathBuf>)>
 where
     T: From<PathBuf> + ConfigFile,
+{
+    process_with(pathnames, T::from)
+}
+
+/// Like `process`, but uses `make` to set up each config file.
+pub fn process_with<T, F>(pathnames: Vec<String>, make: F) -> Result<(Vec<Host>, Vec<PathBuf>)>
+where
+    T: ConfigFile,
+    F: Fn(PathBuf) -> T,
 {
     let mut hosts: Vec<Host> = vec![];
+    let mut read: Vec<PathBuf> = vec![];
     for pn in pathnames {
         let path = PathBuf::from(pn);
-        let file = T::from(path);
+        let file = make(path);
         match file.entries() {
-            Ok(entries) => hosts.extend(entries),
-            Err(e) => println!(
+            Ok(entries) => {
+                hosts.extend(entries);
+                read.push(file.pathname().to_path_buf());
+            }
+            Err(e) => eprintln!(
                 "Could not read config file {:?} ({}), continuing",
                 file.pathname(),
                 e
             ),
         }
     }
-    Ok(hosts)
+    Ok((hosts, read))
 }
 
 #[test]
@@ -207,6 +593,72 @@ fn test_host_creation() {
     assert_eq!(mosh_ohai.from, from);
 }
 
+#[test]
+fn test_host_dedup() {
+    let from = Path::new("/dev/null");
+    let mut first = Host::named("web", from);
+    first.user = Some("deploy".to_string());
+    let mut second = Host::named("web", from);
+    second.port = Some(2222);
+    let mut hosts = vec![
+        first,
+        Host::new("web", "mosh", from),
+        second,
+        Host::named("web", Path::new("/etc/hosts")),
+    ];
+    hosts.sort();
+    hosts.dedup();
+    assert_eq!(hosts.len(), 3);
+    let web = hosts.iter().find(|h| h.from == from && h.protocol == "ssh");
+    assert_eq!(web.unwrap().url(), "ssh://deploy@web");
+}
+
+#[test]
+fn test_host_url() {
+    let from = Path::new("/dev/null");
+    assert_eq!(Host::named("ohai", from).url(), "ssh://ohai");
+
+    let mut bastion = Host::new("bastion", "mosh", from);
+    bastion.user = Some("deploy".to_string());
+    bastion.port = Some(2222);
+    assert_eq!(bastion.url(), "mosh://deploy@bastion:2222");
+
+    bastion.hostname = Some("bastion.example.com".to_string());
+    assert_eq!(bastion.url(), "mosh://deploy@bastion.example.com:2222");
+
+    let mut v6 = Host::named("v6", from);
+    v6.hostname = Some("2001:db8::1".to_string());
+    v6.port = Some(22);
+    assert_eq!(v6.url(), "ssh://[2001:db8::1]:22");
+}
+
+#[test]
+fn test_host_command() {
+    let from = Path::new("/dev/null");
+    assert_eq!(
+        Host::named("ohai", from).command(),
+        vec!["ssh", "--", "ohai"]
+    );
+    assert_eq!(
+        Host::named("-oProxyCommand=sh", from).command(),
+        vec!["ssh", "--", "-oProxyCommand=sh"]
+    );
+
+    let mut bastion = Host::new("bastion", "mosh", from);
+    bastion.user = Some("deploy".to_string());
+    bastion.port = Some(2222);
+    bastion.hostname = Some("bastion.example.com".to_string());
+    assert_eq!(
+        bastion.command(),
+        vec!["mosh", "--ssh=ssh -p 2222", "--", "deploy@bastion"]
+    );
+    bastion.protocol = "ssh".to_string();
+    assert_eq!(
+        bastion.command(),
+        vec!["ssh", "-p", "2222", "--", "deploy@bastion"]
+    );
+}
+
 #[test]
 fn test_host_eligibility() {
     let from = Path::new("/dev/null");
@@ -223,6 +675,77 @@ fn test_host_eligibility() {
     );
 }
 
+#[test]
+fn test_host_kinds() {
+    let from = Path::new("/dev/null");
+    let kind = |name| Host::named(name, from).kind();
+    assert_eq!(kind("web.example.com"), HostKind::Name);
+    assert_eq!(kind("192.0.2.53"), HostKind::Ipv4);
+    assert_eq!(kind("2001:db8::1"), HostKind::Ipv6);
+    assert_eq!(kind("169.254.10.1"), HostKind::LinkLocal);
+    assert_eq!(kind("fe80::1%en0"), HostKind::LinkLocal);
+    assert_eq!(kind("10.0.0.*"), HostKind::Name);
+
+    // Addresses from ssh_config were written there on purpose:
+    let conds = Conditions::default();
+    assert!(!Host::named("192.168.1.10", from).ineligible(&conds));
+    // Addresses from known_hosts are out unless a condition lets them in:
+    let mut address = Host::named("192.0.2.53", from);
+    address.known_host = true;
+    assert!(address.ineligible(&conds));
+    assert!(conds.verdict(&address).address);
+    assert!(!address.ineligible(&Conditions::default().with_addresses(true)));
+    let mut conds = Conditions::default();
+    let (path, cond) = Condition::include_from("*,cidr:192.0.2.0/24").unwrap();
+    conds.add(path, cond);
+    assert!(!address.ineligible(&conds));
+}
+
+#[test]
+fn test_expand_wildcards() {
+    let config = Path::new("/etc/ssh/ssh_config");
+    let known = Path::new("/etc/ssh/ssh_known_hosts");
+
+    let mut db_ssh = Host::new("*.db.example.com", "ssh", config);
+    db_ssh.user = Some("dba".to_string());
+    db_ssh.hostname = Some("%h.internal".to_string());
+    db_ssh.exceptions = vec!["!old.db.example.com".to_string()];
+    let mut db_mosh = Host::new("*.db.example.com", "mosh", config);
+    db_mosh.user = Some("dba".to_string());
+    db_mosh.exceptions = vec!["!old.db.example.com".to_string()];
+    let mut everything = Host::new("*", "ssh", config);
+    everything.user = Some("me".to_string());
+    everything.port = Some(2222);
+    let mut web = Host::new("web.*", "mosh", config);
+    web.hostname = Some("%%h.%h".to_string());
+
+    let mut hosts = expand_wildcards(vec![
+        Host::named("db1.db.example.com", known),
+        Host::named("old.db.example.com", known),
+        Host::named("web.example.com", known),
+        db_ssh,
+        db_mosh,
+        web,
+        everything,
+    ]);
+    hosts.retain(|h| !h.is_wildcard());
+    hosts.sort();
+    let urls: Vec<String> = hosts.iter().map(|h| h.url()).collect();
+    assert_eq!(
+        urls,
+        vec![
+            "mosh://dba@db1.db.example.com",
+            "ssh://dba@db1.db.example.com.internal:2222",
+            "ssh://me@old.db.example.com:2222",
+            "mosh://%h.web.example.com",
+            "ssh://me@web.example.com:2222",
+        ]
+    );
+    assert_eq!(hosts[0].from, config);
+    assert_eq!(hosts[1].from, known);
+    assert_eq!(hosts[3].hostname, Some("%h.web.example.com".to_string()));
+}
+
 #[test]
 fn test_conditions_match() {
     let from = Path::new("/dev/null");
@@ -238,7 +761,10 @@ fn test_conditions_match() {
         let mut conds = Conditions::default();
         conds.add(
             from.to_path_buf(),
-            Condition::Include(Regex::new(r"^foo\.").unwrap()),
+            Condition::Include {
+                protocol: None,
+                matcher: Matcher::parse(r"^foo\.").unwrap(),
+            },
         );
         assert!(conds.eligible(&host));
     }
@@ -247,7 +773,10 @@ fn test_conditions_match() {
         let mut conds = Conditions::default();
         conds.add(
             from.to_path_buf(),
-            Condition::Exclude(Regex::new(r"^foo\.").unwrap()),
+            Condition::Exclude {
+                protocol: None,
+                matcher: Matcher::parse(r"^foo\.").unwrap(),
+            },
         );
         assert!(!conds.eligible(&host));
     }
@@ -256,7 +785,10 @@ fn test_conditions_match() {
         let mut conds = Conditions::default();
         conds.add(
             from.to_path_buf(),
-            Condition::Exclude(Regex::new(r"^baz\.").unwrap()),
+            Condition::Exclude {
+                protocol: None,
+                matcher: Matcher::parse(r"^baz\.").unwrap(),
+            },
         );
         assert!(conds.eligible(&host));
     }
@@ -265,12 +797,112 @@ fn test_conditions_match() {
         let mut conds = Conditions::default();
         conds.add(
             from.to_path_buf(),
-            Condition::Exclude(Regex::new(r"^baz\.").unwrap()),
+            Condition::Exclude {
+                protocol: None,
+                matcher: Matcher::parse(r"^baz\.").unwrap(),
+            },
         );
         conds.add(
             from.to_path_buf(),
-            Condition::Include(Regex::new(r"^qux\.").unwrap()),
+            Condition::Include {
+                protocol: None,
+                matcher: Matcher::parse(r"^qux\.").unwrap(),
+            },
+        );
+        assert!(!conds.eligible(&host));
+    }
+    // Conditions for any file apply after the ones for the host's file:
+    {
+        let mut conds = Conditions::default();
+        conds.add(
+            PathBuf::from(ANY_FILE),
+            Condition::Exclude {
+                protocol: None,
+                matcher: Matcher::parse(r"\.com$").unwrap(),
+            },
         );
         assert!(!conds.eligible(&host));
+        assert!(!conds.eligible(&Host::named("foo.bar.com", Path::new("/etc/hosts"))));
+        conds.add(
+            from.to_path_buf(),
+            Condition::Include {
+                protocol: None,
+                matcher: Matcher::parse(r"^foo\.").unwrap(),
+            },
+        );
+        assert!(conds.eligible(&host));
+        assert!(conds.unread(vec![from]).is_empty());
     }
 }
+
+#[test]
+fn test_conditions_protocol() {
+    let from = Path::new("/dev/null");
+    let ssh = Host::named("files.storage", from);
+    let sftp = Host::new("files.storage", "sftp", from);
+    let mosh = Host::new("web", "mosh", from);
+
+    let mut conds = Conditions::default();
+    let (path, cond) = Condition::exclude_from("/dev/null,mosh://").unwrap();
+    assert_eq!(cond.to_string(), "exclude mosh://");
+    conds.add(path, cond);
+    let (path, cond) = Condition::include_from("/dev/null,sftp://glob:*.storage").unwrap();
+    conds.add(path, cond);
+    let (path, cond) = Condition::include_from("/dev/null,^web").unwrap();
+    conds.add(path, cond);
+    assert!(!conds.eligible(&ssh));
+    assert!(conds.eligible(&sftp));
+    assert!(!conds.eligible(&mosh));
+    assert!(conds.eligible(&Host::named("web", from)));
+
+    // Without an include for all protocols, only the sftp hosts are
+    // limited to the storage ones:
+    let mut conds = Conditions::default();
+    let (path, cond) = Condition::include_from("*,sftp://glob:*.storage").unwrap();
+    conds.add(path, cond);
+    assert!(conds.eligible(&ssh));
+    assert!(conds.eligible(&sftp));
+    assert!(conds.eligible(&mosh));
+    assert!(!conds.eligible(&Host::new("web", "sftp", from)));
+
+    // Things that aren't protocols stay part of the regex:
+    let (_, cond) = Condition::exclude_from("/dev/null,a|b://").unwrap();
+    assert_eq!(cond.to_string(), "exclude a|b://");
+}
+
+#[test]
+fn test_conditions_canonical_paths() {
+    use std::fs;
+
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir(dir.path().join("sub")).unwrap();
+    let config = dir.path().join("config");
+    File::create(&config).unwrap();
+    let host = Host::named("foo.bar.com", &config);
+
+    let mut conds = Conditions::default();
+    conds.add(
+        dir.path().join("sub/../config"),
+        Condition::Exclude {
+            protocol: None,
+            matcher: Matcher::parse(r"^foo\.").unwrap(),
+        },
+    );
+    conds.add(
+        dir.path().join("elsewhere"),
+        Condition::Exclude {
+            protocol: None,
+            matcher: Matcher::parse(r"^bar\.").unwrap(),
+        },
+    );
+    assert!(!conds.eligible(&host));
+    assert_eq!(
+        conds.unread(vec![config.as_path()]),
+        vec![dir
+            .path()
+            .canonicalize()
+            .unwrap()
+            .join("elsewhere")
+            .as_path()]
+    );
+}
@@ -5,12 +5,19 @@ extern crate ssh_bookmarker;
 extern crate error_chain;
 
 use docopt::Docopt;
-use std::path::Path;
+use std::env;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::Command;
 
-use ssh_bookmarker::launchagent;
-use ssh_bookmarker::process;
+use ssh_bookmarker::{
+    bookmarks, completions, explain, inventory, launchagent, pick, suggest, sync,
+};
+use ssh_bookmarker::{expand_wildcards, process, process_with};
 use ssh_bookmarker::{known_hosts, ssh_config};
-use ssh_bookmarker::{Condition, Conditions};
+use ssh_bookmarker::{Condition, Conditions, Host};
 
 use ssh_bookmarker::errors::*;
 
@@ -20,8 +27,14 @@ const USAGE: &str = "
 Create SSH bookmarks from known_hosts and ssh_config files.
 
 Usage:
-  ssh_bookmarker create [-v...] [-c FILE...] [-k FILE...] [-I SPEC...] [-X SPEC...] <output>
-  ssh_bookmarker launchagent [-c FILE...] [-k FILE...] [-I SPEC...] [-X SPEC...] <output>
+  ssh_bookmarker create [-v...] [options] [-c FILE...] [-k FILE...] [-I SPEC...] [-X SPEC...] [--history FILE...] [--wordlist FILE...] <output>
+  ssh_bookmarker launchagent [options] [-c FILE...] [-k FILE...] [-I SPEC...] [-X SPEC...] [--history FILE...] [--wordlist FILE...] <output>
+  ssh_bookmarker list [options] [-c FILE...] [-k FILE...] [-I SPEC...] [-X SPEC...] [--history FILE...] [--wordlist FILE...]
+  ssh_bookmarker hosts [options] [-c FILE...] [-k FILE...] [-I SPEC...] [-X SPEC...] [--history FILE...] [--wordlist FILE...]
+  ssh_bookmarker completions --shell SHELL [options] [-c FILE...] [-k FILE...] [-I SPEC...] [-X SPEC...] [--history FILE...] [--wordlist FILE...]
+  ssh_bookmarker pick [options] [-c FILE...] [-k FILE...] [-I SPEC...] [-X SPEC...] [--history FILE...] [--wordlist FILE...] [--client SPEC...]
+  ssh_bookmarker suggest [options] [-c FILE...] [-k FILE...] [-I SPEC...] [-X SPEC...] [--history FILE...] [--wordlist FILE...] <query>
+  ssh_bookmarker explain [options] [-c FILE...] [-k FILE...] [-I SPEC...] [-X SPEC...] [--history FILE...] [--wordlist FILE...] <hostname>
   ssh_bookmarker --help
 
 Options:
@@ -30,9 +43,64 @@ Options:
   -c --config FILE         ssh_config(5) file to read.
   -k --known-hosts FILE    known_hosts file to read.
   -I --include SPEC        In a given file, include only hosts matching the
-                           given regex. SPEC format is \"FILE,REGEX\".
+                           given regex. SPEC format is \"FILE,REGEX\", and
+                           a FILE of \"*\" means any file. Instead of a
+                           regex, use glob:PATTERNS (like ssh_config Host
+                           patterns), suffix:DOMAIN or cidr:RANGE, and
+                           prefix it with PROTOCOL:// to only match hosts
+                           with that protocol.
   -X --exclude SPEC        Like --include, exclude hosts matching the regex
                            from the file.
+  --resolve-hashed         Recover hashed known_hosts entries by trying the
+                           host names and HostNames from the ssh_config
+                           files, and from any --history and --wordlist.
+  --history FILE           Shell history file to take host names from when
+                           resolving hashed known_hosts entries.
+  --wordlist FILE          File with one host name per line to try when
+                           resolving hashed known_hosts entries.
+  --expand-wildcards       Bookmark the hosts matching wildcard Host blocks
+                           (like `Host *.example.com`) with the protocols
+                           and settings of those blocks.
+  --include-revoked        Bookmark hosts from @revoked known_hosts lines.
+  --cert-authority-only    Only bookmark hosts that match the patterns of a
+                           @cert-authority line in the known_hosts files.
+  --keep-addresses         Bookmark hosts from known_hosts that are IP
+                           addresses, too, even when no -I condition lets
+                           them in.
+  --prefer-names           On known_hosts lines with both names and IP
+                           addresses, leave out the addresses.
+  -f --format FORMAT       Kind of bookmark files to create: webloc,
+                           inetloc, desktop (freedesktop links),
+                           desktop-launcher (freedesktop applications
+                           that run ssh or mosh), url (Windows Internet
+                           Shortcuts), iterm2-dynamic-profiles,
+                           windows-terminal (a profiles fragment),
+                           kitty-session, wezterm-ssh-domains (a Lua
+                           file for the ssh_domains setting) or html (a
+                           bookmarks.html file for browsers). For
+                           suggest, the kind of JSON to print: launchbar
+                           or alfred. For list, one of json, csv or yaml.
+                           Defaults to webloc, launchbar and json.
+  --terminal COMMAND       Terminal emulator command line that the
+                           desktop-launcher entries run ssh or mosh with,
+                           like \"xterm -e\". By default, the desktop
+                           environment picks the terminal.
+  --group-by KEY           Put bookmarks into folders by source file,
+                           protocol, domain or ssh_config Tag. The html
+                           format groups by source file by default.
+  --parent-profile NAME    Terminal profile that generated profiles
+                           inherit their settings from.
+  --names-only             Make hosts print only host names, not URLs.
+  --shell SHELL            Shell to make completions for: bash, zsh or fish.
+  --connect                Make pick read the chosen line from stdin and
+                           connect to that host.
+  --client SPEC            Command that pick connects with for a protocol,
+                           which gets the host's URL as its last argument.
+                           SPEC format is \"PROTOCOL=COMMAND\".
+  -n --dry-run             Only show which bookmarks create would add,
+                           change and remove, without touching <output>.
+  --json                   Like --dry-run, but show the changes as JSON
+                           lines.
 ";
 
 #[derive(Debug, RustcDecodable)]
@@ -40,11 +108,65 @@ struct Args {
     flag_verbose: isize,
     cmd_create: bool,
     cmd_launchagent: bool,
+    cmd_list: bool,
+    cmd_hosts: bool,
+    cmd_completions: bool,
+    cmd_pick: bool,
+    cmd_suggest: bool,
+    cmd_explain: bool,
     arg_output: String,
+    arg_query: String,
+    arg_hostname: String,
     flag_config: Vec<String>,
     flag_known_hosts: Vec<String>,
     flag_include: Vec<String>,
     flag_exclude: Vec<String>,
+    flag_resolve_hashed: bool,
+    flag_history: Vec<String>,
+    flag_wordlist: Vec<String>,
+    flag_expand_wildcards: bool,
+    flag_include_revoked: bool,
+    flag_cert_authority_only: bool,
+    flag_keep_addresses: bool,
+    flag_prefer_names: bool,
+    flag_format: Option<String>,
+    flag_terminal: Option<String>,
+    flag_parent_profile: Option<String>,
+    flag_group_by: Option<String>,
+    flag_names_only: bool,
+    flag_shell: String,
+    flag_connect: bool,
+    flag_client: Vec<String>,
+    flag_dry_run: bool,
+    flag_json: bool,
+}
+
+/// Returns the command line that prints the names of the hosts that
+/// `args` select, for completion scripts to run.
+fn hosts_command(args: &Args) -> Result<Vec<String>> {
+    let exe =
+        env::current_exe().chain_err(|| "Couldn't determine the currently running program")?;
+    let mut command = vec![
+        exe.to_str()
+            .ok_or("How did you get a non-unicodeable executable name?")?
+            .to_string(),
+        "hosts".to_string(),
+        "--names-only".to_string(),
+    ];
+    let files = [
+        ("-c", &args.flag_config),
+        ("-k", &args.flag_known_hosts),
+        ("-I", &args.flag_include),
+        ("-X", &args.flag_exclude),
+    ];
+    for &(flag, values) in files.iter() {
+        for value in values.iter() {
+            command.push(flag.to_string());
+            command.push(value.to_string());
+        }
+    }
+    command.extend(host_options(args));
+    Ok(command)
 }
 
 quick_main!(run);
@@ -53,25 +175,36 @@ fn run() -> Result<()> {
         .and_then(|d| d.decode())
         .unwrap_or_else(|e| e.exit());
     if args.cmd_create {
-        let conds = create_conditions(args.flag_include, args.flag_exclude)?;
-        let mut hosts = process::<known_hosts::KnownHosts>(args.flag_known_hosts)?;
-        hosts.extend(process::<ssh_config::SSHConfigFile>(args.flag_config)?);
+        let conds = create_conditions(&args)?;
+        let mut hosts = collect_hosts(&args, &conds)?;
         hosts.sort();
         hosts.dedup();
 
         let output = Path::new(&args.arg_output);
-        std::fs::remove_dir_all(output)
-            .chain_err(|| format!("Could not clear output directory {:?}", output))?;
-        std::fs::create_dir_all(output)
-            .chain_err(|| format!("Couldn't re-create output directory {:?}", output))?;
-
-        for kh in hosts {
-            if kh.ineligible(&conds) {
-                continue;
+        let options = bookmarks::Options {
+            terminal: args.flag_terminal.clone(),
+            parent_profile: args.flag_parent_profile.clone(),
+            group_by: match args.flag_group_by {
+                Some(ref key) => Some(bookmarks::Grouping::from_name(key)?),
+                None => None,
+            },
+        };
+        let format = args.flag_format.as_ref().map_or("webloc", |f| f.as_str());
+        let writer = bookmarks::writer(format, &options)?;
+        let eligible: Vec<&Host> = hosts.iter().filter(|kh| !kh.ineligible(&conds)).collect();
+        let wanted = writer.bookmarks(&eligible)?;
+        let plan = sync::plan(output, wanted)
+            .chain_err(|| format!("Couldn't read output directory {:?}", output))?;
+        if args.flag_dry_run || args.flag_json {
+            let stdout = io::stdout();
+            if args.flag_json {
+                plan.write_json_lines(&mut stdout.lock())?;
+            } else {
+                plan.write_summary(&mut stdout.lock())?;
             }
-            kh.write_bookmark(output)
-                .chain_err(|| format!("Couldn't write bookmark {:?}", kh))?;
+            return Ok(());
         }
+        sync::apply(output, &plan)?;
         Ok(())
     } else if args.cmd_launchagent {
         println!(
@@ -81,24 +214,290 @@ fn run() -> Result<()> {
                 &args.flag_known_hosts,
                 &args.flag_include,
                 &args.flag_exclude,
+                &create_options(&args),
                 &args.arg_output
             )?
         );
         Ok(())
+    } else if args.cmd_list {
+        let conds = create_conditions(&args)?;
+        let mut hosts = collect_hosts(&args, &conds)?;
+        hosts.sort();
+        hosts.dedup();
+        let records: Vec<inventory::Record> = hosts
+            .iter()
+            .map(|h| inventory::Record::new(h, &conds))
+            .collect();
+        let format = args.flag_format.as_ref().map_or("json", |f| f.as_str());
+        let stdout = io::stdout();
+        inventory::write(format, &records, stdout.lock())?;
+        Ok(())
+    } else if args.cmd_hosts {
+        let conds = create_conditions(&args)?;
+        let mut hosts = collect_hosts(&args, &conds)?;
+        hosts.retain(|h| !h.ineligible(&conds));
+        let mut lines: Vec<String> = if args.flag_names_only {
+            hosts.iter().map(|h| h.name().to_string()).collect()
+        } else {
+            hosts.iter().map(|h| h.url()).collect()
+        };
+        lines.sort();
+        lines.dedup();
+        let stdout = io::stdout();
+        let mut out = stdout.lock();
+        for line in lines {
+            writeln!(out, "{}", line)?;
+        }
+        Ok(())
+    } else if args.cmd_completions {
+        print!(
+            "{}",
+            completions::script(&args.flag_shell, &hosts_command(&args)?)?
+        );
+        Ok(())
+    } else if args.cmd_pick {
+        let conds = create_conditions(&args)?;
+        let mut hosts = collect_hosts(&args, &conds)?;
+        hosts.retain(|h| !h.ineligible(&conds));
+        hosts.sort();
+        let eligible: Vec<&Host> = hosts.iter().collect();
+        if !args.flag_connect {
+            let mut entries: Vec<String> = eligible.iter().map(|h| pick::entry(h)).collect();
+            entries.dedup();
+            let stdout = io::stdout();
+            let mut out = stdout.lock();
+            for entry in entries {
+                writeln!(out, "{}", entry)?;
+            }
+            return Ok(());
+        }
+
+        let mut clients = pick::Clients::default();
+        for spec in args.flag_client.iter() {
+            clients.add_spec(spec)?;
+        }
+        let mut line = String::new();
+        io::stdin().read_line(&mut line)?;
+        if line.trim().is_empty() {
+            // Nothing was picked:
+            return Ok(());
+        }
+        let host = pick::chosen(&eligible, &line)
+            .ok_or_else(|| format!("No host matches {:?}", line.trim_end()))?;
+        connect(&clients.command(host)?)
+    } else if args.cmd_suggest {
+        let conds = create_conditions(&args)?;
+        let hosts = collect_hosts(&args, &conds)?;
+        let eligible: Vec<&Host> = hosts.iter().filter(|kh| !kh.ineligible(&conds)).collect();
+        let found = suggest::suggestions(&eligible, &args.arg_query);
+        match args
+            .flag_format
+            .as_ref()
+            .map_or("launchbar", |f| f.as_str())
+        {
+            "launchbar" => println!("{}", suggest::launchbar_items(&found)),
+            "alfred" => println!("{}", suggest::alfred_items(&found)),
+            other => bail!(ErrorKind::UnknownFormat(other.to_string())),
+        }
+        Ok(())
+    } else if args.cmd_explain {
+        let conds = create_conditions(&args)?;
+        let hosts = collect_hosts(&args, &conds)?;
+        let found: Vec<&Host> = hosts
+            .iter()
+            .filter(|h| h.name() == args.arg_hostname)
+            .collect();
+        if found.is_empty() {
+            bail!(
+                "No host named {:?} was found in any of the files",
+                args.arg_hostname
+            );
+        }
+        let stdout = io::stdout();
+        explain::write(&found, &conds, stdout.lock())?;
+        Ok(())
     } else {
         bail!("Don't know what to do!");
     }
 }
 
-fn create_conditions(include: Vec<String>, exclude: Vec<String>) -> Result<Conditions> {
-    let mut conds = Conditions::default();
-    for inc in include.into_iter() {
-        let (pn, cond) = Condition::include_from(&inc)?;
+/// Runs `command` in place of ssh_bookmarker. Since its standard input
+/// was the picker's output, the command gets the terminal instead.
+#[cfg(unix)]
+fn connect(command: &[String]) -> Result<()> {
+    use std::os::unix::process::CommandExt;
+
+    let mut cmd = Command::new(&command[0]);
+    cmd.args(&command[1..]);
+    if let Ok(tty) = File::open("/dev/tty") {
+        cmd.stdin(tty);
+    }
+    Err(cmd.exec()).chain_err(|| format!("Couldn't run {:?}", command[0]))
+}
+
+#[cfg(not(unix))]
+fn connect(command: &[String]) -> Result<()> {
+    let status = Command::new(&command[0])
+        .args(&command[1..])
+        .status()
+        .chain_err(|| format!("Couldn't run {:?}", command[0]))?;
+    ::std::process::exit(status.code().unwrap_or(1))
+}
+
+/// Reads the hosts from all the config and known_hosts files, in the
+/// order they were found, and warns about the `conds` that can't apply.
+fn collect_hosts(args: &Args, conds: &Conditions) -> Result<Vec<Host>> {
+    let (config_hosts, mut read) = process::<ssh_config::SSHConfigFile>(args.flag_config.clone())?;
+    let authorities = cert_authority_patterns(&args.flag_known_hosts);
+    let candidates = if args.flag_resolve_hashed {
+        hashed_candidates(args, &config_hosts, &authorities)?
+    } else {
+        if !args.flag_history.is_empty() || !args.flag_wordlist.is_empty() {
+            eprintln!("Warning: --history and --wordlist are only used with --resolve-hashed");
+        }
+        vec![]
+    };
+    let (known_hosts, known_hosts_read) = process_with(args.flag_known_hosts.clone(), |path| {
+        known_hosts::KnownHosts::from(path)
+            .with_candidates(&candidates)
+            .with_revoked(args.flag_include_revoked)
+            .with_preferred_names(args.flag_prefer_names)
+    })?;
+    read.extend(known_hosts_read);
+    let mut hosts = config_hosts;
+    hosts.extend(known_hosts);
+    warn_unread(&read, conds, &hosts);
+    if args.flag_expand_wildcards {
+        hosts = expand_wildcards(hosts);
+    }
+    if args.flag_cert_authority_only {
+        if authorities.is_empty() {
+            eprintln!(
+                "Warning: --cert-authority-only, but there are no @cert-authority lines, \
+                 so no host gets a bookmark"
+            );
+        }
+        hosts.retain(|h| h.matches_any(&authorities));
+    }
+    Ok(hosts)
+}
+
+/// Warns about the conditions for files that weren't `read` and that
+/// no host came from, since they can't apply.
+fn warn_unread(read: &[PathBuf], conds: &Conditions, hosts: &[Host]) {
+    let read = read
+        .iter()
+        .map(|p| p.as_path())
+        .chain(hosts.iter().map(|h| h.from()));
+    for path in conds.unread(read) {
+        eprintln!(
+            "Warning: {:?} was never read, so its -I/-X conditions don't apply",
+            path
+        );
+    }
+}
+
+fn create_conditions(args: &Args) -> Result<Conditions> {
+    let mut conds = Conditions::default().with_addresses(args.flag_keep_addresses);
+    for inc in args.flag_include.iter() {
+        let (pn, cond) = Condition::include_from(inc)?;
         conds.add(pn, cond);
     }
-    for exc in exclude.into_iter() {
-        let (pn, cond) = Condition::exclude_from(&exc)?;
+    for exc in args.flag_exclude.iter() {
+        let (pn, cond) = Condition::exclude_from(exc)?;
         conds.add(pn, cond);
     }
     Ok(conds)
 }
+
+/// Collects the host patterns of the @cert-authority lines in all
+/// known_hosts files.
+fn cert_authority_patterns(pathnames: &[String]) -> Vec<String> {
+    let mut patterns = vec![];
+    for pn in pathnames {
+        // Unreadable files get reported when their hosts are read:
+        if let Ok(found) =
+            known_hosts::KnownHosts::from(PathBuf::from(pn)).cert_authority_patterns()
+        {
+            patterns.extend(found);
+        }
+    }
+    patterns
+}
+
+/// Collects the names to try on hashed known_hosts entries.
+fn hashed_candidates(
+    args: &Args,
+    config_hosts: &[Host],
+    authorities: &[String],
+) -> Result<Vec<String>> {
+    let mut candidates = known_hosts::config_candidates(config_hosts);
+    for pn in args.flag_history.iter() {
+        let f = File::open(pn).chain_err(|| format!("Couldn't open history file {:?}", pn))?;
+        candidates.extend(known_hosts::history_candidates(BufReader::new(f))?);
+    }
+    for pn in args.flag_wordlist.iter() {
+        let f = File::open(pn).chain_err(|| format!("Couldn't open wordlist {:?}", pn))?;
+        candidates.extend(known_hosts::wordlist_candidates(BufReader::new(f))?);
+    }
+    let domains = known_hosts::cert_authority_domains(authorities);
+    let mut candidates = known_hosts::qualify_candidates(&candidates, &domains);
+    candidates.sort();
+    candidates.dedup();
+    Ok(candidates)
+}
+
+/// Returns the options that decide which hosts are found, other than
+/// the files and conditions.
+fn host_options(args: &Args) -> Vec<String> {
+    let mut options = vec![];
+    if args.flag_resolve_hashed {
+        options.push("--resolve-hashed".to_string());
+    }
+    for pn in args.flag_history.iter() {
+        options.push("--history".to_string());
+        options.push(pn.to_string());
+    }
+    for pn in args.flag_wordlist.iter() {
+        options.push("--wordlist".to_string());
+        options.push(pn.to_string());
+    }
+    if args.flag_expand_wildcards {
+        options.push("--expand-wildcards".to_string());
+    }
+    if args.flag_include_revoked {
+        options.push("--include-revoked".to_string());
+    }
+    if args.flag_cert_authority_only {
+        options.push("--cert-authority-only".to_string());
+    }
+    if args.flag_keep_addresses {
+        options.push("--keep-addresses".to_string());
+    }
+    if args.flag_prefer_names {
+        options.push("--prefer-names".to_string());
+    }
+    options
+}
+
+/// Returns the `create` options that the LaunchAgent should pass on.
+fn create_options(args: &Args) -> Vec<String> {
+    let mut options = host_options(args);
+    if let Some(ref format) = args.flag_format {
+        options.push("--format".to_string());
+        options.push(format.to_string());
+    }
+    if let Some(ref terminal) = args.flag_terminal {
+        options.push("--terminal".to_string());
+        options.push(terminal.to_string());
+    }
+    if let Some(ref key) = args.flag_group_by {
+        options.push("--group-by".to_string());
+        options.push(key.to_string());
+    }
+    if let Some(ref parent) = args.flag_parent_profile {
+        options.push("--parent-profile".to_string());
+        options.push(parent.to_string());
+    }
+    options
+}
@@ -0,0 +1,151 @@
+use errors::*;
+use regex::Regex;
+use ssh_config::pattern_list_matches;
+use std::fmt;
+use std::net::IpAddr;
+
+/// What the hosts named in a condition spec have to look like. The
+/// kind is picked by a prefix: `glob:`, `suffix:`, `cidr:` or
+/// `regex:`, and specs without one of those are regexes.
+#[derive(Debug)]
+pub enum Matcher {
+    Regex(Regex),
+    /// ssh_config(5) `Host` patterns, separated by commas or spaces,
+    /// where `!` negates a pattern.
+    Glob(Vec<String>),
+    /// A domain and all the names under it, like `example.com` for
+    /// `example.com` and `www.example.com` (but not `badexample.com`).
+    Suffix(String),
+    /// A range of IP addresses. Only hosts named by an IP literal match.
+    Cidr(IpAddr, u8),
+}
+
+impl Matcher {
+    pub fn parse(spec: &str) -> Result<Matcher> {
+        if let Some(patterns) = spec.strip_prefix("glob:") {
+            let patterns: Vec<String> = patterns
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|p| !p.is_empty())
+                .map(|p| p.to_string())
+                .collect();
+            if patterns.is_empty() {
+                bail!(ErrorKind::ConditionFormat(spec.to_string()));
+            }
+            Ok(Matcher::Glob(patterns))
+        } else if let Some(domain) = spec.strip_prefix("suffix:") {
+            let domain = domain.trim_matches('.').to_lowercase();
+            if domain.is_empty() {
+                bail!(ErrorKind::ConditionFormat(spec.to_string()));
+            }
+            Ok(Matcher::Suffix(domain))
+        } else if let Some(range) = spec.strip_prefix("cidr:") {
+            parse_cidr(range).ok_or_else(|| ErrorKind::RangeFormat(range.to_string()).into())
+        } else {
+            let pattern = spec.strip_prefix("regex:").unwrap_or(spec);
+            Ok(Matcher::Regex(
+                Regex::new(pattern).chain_err(|| "could not parse the host regex")?,
+            ))
+        }
+    }
+
+    pub fn is_match(&self, name: &str) -> bool {
+        match *self {
+            Matcher::Regex(ref pat) => pat.is_match(name),
+            Matcher::Glob(ref patterns) => pattern_list_matches(patterns, name),
+            Matcher::Suffix(ref domain) => {
+                let name = name.trim_end_matches('.').to_lowercase();
+                name == *domain || name.ends_with(&format!(".{}", domain))
+            }
+            Matcher::Cidr(network, prefix) => match ip_literal(name) {
+                Some(addr) => in_range(addr, network, prefix),
+                None => false,
+            },
+        }
+    }
+}
+
+impl fmt::Display for Matcher {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Matcher::Regex(ref pat) => write!(f, "{}", pat),
+            Matcher::Glob(ref patterns) => write!(f, "glob:{}", patterns.join(",")),
+            Matcher::Suffix(ref domain) => write!(f, "suffix:{}", domain),
+            Matcher::Cidr(network, prefix) => write!(f, "cidr:{}/{}", network, prefix),
+        }
+    }
+}
+
+/// Parses a range like `10.0.0.0/8` or `fd00::/8`. A bare address is a
+/// range of one.
+fn parse_cidr(range: &str) -> Option<Matcher> {
+    let (addr, prefix) = match range.split_once('/') {
+        Some((addr, prefix)) => (
+            addr.parse::<IpAddr>().ok()?,
+            Some(prefix.parse::<u8>().ok()?),
+        ),
+        None => (range.parse::<IpAddr>().ok()?, None),
+    };
+    let bits = if addr.is_ipv4() { 32 } else { 128 };
+    let prefix = prefix.unwrap_or(bits);
+    if prefix > bits {
+        return None;
+    }
+    Some(Matcher::Cidr(addr, prefix))
+}
+
+/// Returns the address that a host name spells out, if it's an IP
+/// literal. Brackets and IPv6 zones (`fe80::1%en0`) are allowed.
+pub fn ip_literal(name: &str) -> Option<IpAddr> {
+    let name = name.trim_start_matches('[').trim_end_matches(']');
+    let name = name.split('%').next().unwrap_or(name);
+    name.parse().ok()
+}
+
+fn in_range(addr: IpAddr, network: IpAddr, prefix: u8) -> bool {
+    let (addr, network, bits) = match (addr, network) {
+        (IpAddr::V4(a), IpAddr::V4(n)) => (u32::from(a) as u128, u32::from(n) as u128, 32),
+        (IpAddr::V6(a), IpAddr::V6(n)) => (u128::from(a), u128::from(n), 128),
+        _ => return false,
+    };
+    let shift = bits - u32::from(prefix);
+    shift == bits || addr >> shift == network >> shift
+}
+
+#[test]
+fn test_matchers() {
+    let regex = Matcher::parse(r"^web\d").unwrap();
+    assert!(regex.is_match("web1.example.com"));
+    assert!(!regex.is_match("db1.example.com"));
+    assert!(Matcher::parse("regex:^glob:").unwrap().is_match("glob:x"));
+    assert!(Matcher::parse("regex:(").is_err());
+
+    let glob = Matcher::parse("glob:*.prod.example.com,!legacy.*").unwrap();
+    assert!(glob.is_match("web.PROD.example.com"));
+    assert!(!glob.is_match("legacy.prod.example.com"));
+    assert!(!glob.is_match("prod.example.com"));
+    assert!(Matcher::parse("glob:").is_err());
+
+    let suffix = Matcher::parse("suffix:.example.com").unwrap();
+    assert!(suffix.is_match("example.com"));
+    assert!(suffix.is_match("www.Example.com."));
+    assert!(!suffix.is_match("badexample.com"));
+    assert!(Matcher::parse("suffix:").is_err());
+
+    let v4 = Matcher::parse("cidr:10.1.0.0/16").unwrap();
+    assert!(v4.is_match("10.1.2.3"));
+    assert!(!v4.is_match("10.2.0.1"));
+    assert!(!v4.is_match("10.1.example.com"));
+    assert!(!v4.is_match("::ffff:10.1.2.3"));
+    let v6 = Matcher::parse("cidr:fe80::/10").unwrap();
+    assert!(v6.is_match("fe80::1%en0"));
+    assert!(v6.is_match("[fe80::1]"));
+    assert!(!v6.is_match("2001:db8::1"));
+    assert!(Matcher::parse("cidr:0.0.0.0/0")
+        .unwrap()
+        .is_match("192.0.2.1"));
+    assert!(Matcher::parse("cidr:192.0.2.1")
+        .unwrap()
+        .is_match("192.0.2.1"));
+    assert!(Matcher::parse("cidr:10.0.0.0/33").is_err());
+    assert!(Matcher::parse("cidr:example.com/8").is_err());
+}
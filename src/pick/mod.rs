@@ -0,0 +1,89 @@
+use errors::*;
+use std::collections::HashMap;
+use Host;
+
+/// Returns the line that stands for `host` in a picker.
+pub fn entry(host: &Host) -> String {
+    format!("{} ({})", host.name, host.protocol)
+}
+
+/// Returns the host whose entry was picked, if any.
+pub fn chosen<'a>(hosts: &[&'a Host], line: &str) -> Option<&'a Host> {
+    let line = line.trim_end_matches(&['\r', '\n'][..]);
+    hosts.iter().find(|h| entry(h) == line).cloned()
+}
+
+/// The programs that connect to hosts, by protocol.
+#[derive(Debug, Default)]
+pub struct Clients {
+    commands: HashMap<String, Vec<String>>,
+}
+
+impl Clients {
+    /// Adds a client from a spec like `sftp=lftp -d`: the command is
+    /// split into words on whitespace, and gets the URL of the host as
+    /// its last argument.
+    pub fn add_spec(&mut self, spec: &str) -> Result<()> {
+        let (protocol, command) = spec
+            .split_once('=')
+            .ok_or_else(|| ErrorKind::ClientFormat(spec.to_string()))?;
+        let command: Vec<String> = command.split_whitespace().map(|w| w.to_string()).collect();
+        if protocol.is_empty() || command.is_empty() {
+            bail!(ErrorKind::ClientFormat(spec.to_string()));
+        }
+        self.commands.insert(protocol.to_string(), command);
+        Ok(())
+    }
+
+    /// Returns the command line that connects to `host`: the configured
+    /// client for its protocol, or else ssh, mosh or sftp with the
+    /// host's user and port.
+    pub fn command(&self, host: &Host) -> Result<Vec<String>> {
+        match self.commands.get(&host.protocol) {
+            Some(client) => {
+                let mut command = client.clone();
+                command.push(host.url());
+                Ok(command)
+            }
+            None if ["ssh", "mosh", "sftp"].contains(&host.protocol.as_str()) => Ok(host.command()),
+            None => bail!(ErrorKind::NoClient(host.protocol.to_string())),
+        }
+    }
+}
+
+#[test]
+fn test_pick() {
+    use std::path::Path;
+
+    let from = Path::new("/dev/null");
+    let mut web = Host::new("web (old)", "mosh", from);
+    web.user = Some("me".to_string());
+    web.port = Some(2222);
+    let files = Host::new("files", "sftp", from);
+    let vnc = Host::new("desktop", "vnc", from);
+    let hosts = [&web, &files, &vnc];
+
+    assert_eq!(entry(&web), "web (old) (mosh)");
+    assert_eq!(chosen(&hosts, "web (old) (mosh)\n"), Some(&web));
+    assert_eq!(chosen(&hosts, "web (old) (ssh)\n"), None);
+
+    let mut clients = Clients::default();
+    assert_eq!(
+        clients.command(&web).unwrap(),
+        vec!["mosh", "--ssh=ssh -p 2222", "--", "me@web (old)"]
+    );
+    assert_eq!(
+        clients.command(&files).unwrap(),
+        vec!["sftp", "--", "files"]
+    );
+    assert!(clients.command(&vnc).is_err());
+
+    clients.add_spec("vnc=vncviewer -Shared").unwrap();
+    assert_eq!(
+        clients.command(&vnc).unwrap(),
+        vec!["vncviewer", "-Shared", "vnc://desktop"]
+    );
+    assert!(clients.add_spec("vnc").is_err());
+    assert!(clients.add_spec("=ssh").is_err());
+    assert!(clients.add_spec("vnc= ").is_err());
+}
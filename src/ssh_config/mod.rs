@@ -1,51 +1,571 @@
 extern crate regex;
 
 use errors::*;
+use glob::glob;
+use std::env;
+use std::fs::File;
 use std::io::prelude::*;
+use std::io::BufReader;
 use std::path::{Path, PathBuf};
 use {ConfigFile, Host};
 
 pub struct SSHConfigFile {
     pathname: PathBuf,
+    include_dir: PathBuf,
 }
 
 impl From<PathBuf> for SSHConfigFile {
     fn from(path: PathBuf) -> SSHConfigFile {
-        SSHConfigFile { pathname: path }
+        let include_dir = default_include_dir(&path);
+        SSHConfigFile {
+            pathname: path,
+            include_dir,
+        }
     }
 }
 
-impl ConfigFile for SSHConfigFile {
-    fn pathname(&self) -> &Path {
-        self.pathname.as_path()
+/// Returns the directory that relative `Include` paths are resolved
+/// against: like OpenSSH, that is `/etc/ssh` for the system-wide
+/// config and `~/.ssh` for everything else.
+fn default_include_dir(path: &Path) -> PathBuf {
+    if path.starts_with("/etc/ssh") {
+        return PathBuf::from("/etc/ssh");
+    }
+    match env::var_os("HOME") {
+        Some(home) => Path::new(&home).join(".ssh"),
+        None => path
+            .parent()
+            .map(|p| p.to_path_buf())
+            .unwrap_or_else(|| PathBuf::from(".")),
     }
+}
 
-    fn parse_entries<R: BufRead>(&self, file: R) -> Result<Vec<Host>> {
-        let mut hosts: Vec<Host> = vec![];
-        for maybe_line in file.lines() {
+/// Expands a leading `~/` in an `Include` argument to the user's home
+/// directory.
+fn expand_tilde(pattern: &str) -> PathBuf {
+    if let (Some(rest), Some(home)) = (pattern.strip_prefix("~/"), env::var_os("HOME")) {
+        return Path::new(&home).join(rest);
+    }
+    PathBuf::from(pattern)
+}
+
+impl SSHConfigFile {
+    /// Resolve relative `Include` paths against `dir` instead of the
+    /// default (`~/.ssh`).
+    pub fn with_include_dir(mut self, dir: &Path) -> SSHConfigFile {
+        self.include_dir = dir.to_path_buf();
+        self
+    }
+
+    /// Returns the files matching an `Include` argument, in the
+    /// (lexically sorted) order that ssh reads them.
+    fn included_files(&self, pattern: &str) -> Result<Vec<PathBuf>> {
+        let mut path = expand_tilde(pattern);
+        if path.is_relative() {
+            path = self.include_dir.join(path);
+        }
+        let pattern = path
+            .to_str()
+            .ok_or_else(|| format!("Include path {:?} is not valid unicode", path))?;
+        let mut paths: Vec<PathBuf> = glob(pattern)?.filter_map(|p| p.ok()).collect();
+        paths.sort();
+        Ok(paths)
+    }
+
+    /// Reads the lines of `file` into `blocks`. Lines before the first
+    /// `Host` line belong to the block at index `current`, just like
+    /// ssh applies the lines at the top of an `Include`d file to the
+    /// block that the `Include` appeared in. `including` holds the
+    /// files whose `Include` lines led to this one.
+    fn parse_file<R: BufRead>(
+        &self,
+        pathname: &Path,
+        file: R,
+        including: &mut Vec<PathBuf>,
+        blocks: &mut Vec<Block>,
+        current: usize,
+    ) -> Result<()> {
+        let mut current = current;
+        for (lineno, maybe_line) in file.lines().enumerate() {
             let line = try!(maybe_line);
+            let format_error =
+                || ErrorKind::ConfigFormat(pathname.to_path_buf(), lineno + 1, line.to_string());
+            let directive = match tokenize(&line) {
+                Some(Ok(directive)) => directive,
+                Some(Err(ref keyword)) if keyword == "host" || keyword == "include" => {
+                    bail!(format_error())
+                }
+                Some(Err(_)) => {
+                    // Other settings don't decide which hosts there are,
+                    // so a broken one only costs that one line:
+                    eprintln!(
+                        "Warning: {} line {}: can't parse {:?}, skipping it",
+                        pathname.display(),
+                        lineno + 1,
+                        line
+                    );
+                    continue;
+                }
+                None => continue,
+            };
+
+            match directive.keyword.as_str() {
+                "include" => {
+                    for pattern in directive.args.iter() {
+                        for included in self.included_files(pattern)? {
+                            // A file can be included more than once, but
+                            // not by itself:
+                            let included_canonical = canonical(&included);
+                            if including.contains(&included_canonical) {
+                                continue;
+                            }
+                            let f = match File::open(&included) {
+                                Ok(f) => f,
+                                Err(e) => {
+                                    eprintln!(
+                                        "Could not read included config file {:?} ({}), continuing",
+                                        included, e
+                                    );
+                                    continue;
+                                }
+                            };
+                            including.push(included_canonical);
+                            let parsed = self.parse_file(
+                                &included,
+                                BufReader::new(f),
+                                including,
+                                blocks,
+                                current,
+                            );
+                            including.pop();
+                            if let Err(e) = parsed {
+                                eprintln!(
+                                    "Could not parse included config file {:?} ({}), continuing",
+                                    included, e
+                                );
+                            }
+                        }
+                    }
+                }
+                "host" => {
+                    blocks.push(Block {
+                        selector: Selector::Host(directive.args),
+                        protocols: directive.protocols,
+                        from: pathname.to_path_buf(),
+                        line: Some(lineno + 1),
+                        settings: Settings::default(),
+                    });
+                    current = blocks.len() - 1;
+                }
+                "match" => {
+                    let criteria = parse_criteria(directive.args).ok_or_else(format_error)?;
+                    blocks.push(Block {
+                        selector: Selector::Match(criteria),
+                        protocols: vec![],
+                        from: pathname.to_path_buf(),
+                        line: Some(lineno + 1),
+                        settings: Settings::default(),
+                    });
+                    current = blocks.len() - 1;
+                }
+                keyword => {
+                    let settings = &mut blocks[current].settings;
+                    let value = directive.args.into_iter().next();
+                    match keyword {
+                        "hostname" if settings.hostname.is_none() => settings.hostname = value,
+                        "user" if settings.user.is_none() => settings.user = value,
+                        "tag" if settings.tag.is_none() => settings.tag = value,
+                        "port" if settings.port.is_none() => {
+                            settings.port = value.as_ref().and_then(|p| p.parse().ok());
+                            if settings.port.is_none() {
+                                eprintln!(
+                                    "Warning: {} line {}: {:?} is not a port, ignoring it",
+                                    pathname.display(),
+                                    lineno + 1,
+                                    value.unwrap_or_default()
+                                );
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The connection settings from an ssh_config file that end up in
+/// bookmarks.
+#[derive(Debug, Default, Clone, PartialEq)]
+struct Settings {
+    hostname: Option<String>,
+    user: Option<String>,
+    port: Option<u16>,
+    tag: Option<String>,
+}
+
+impl Settings {
+    /// Fills in the settings that aren't set yet from `other`: like in
+    /// ssh, the first value obtained for each setting wins.
+    fn merge(&mut self, other: &Settings) {
+        if self.hostname.is_none() {
+            self.hostname = other.hostname.clone();
+        }
+        if self.user.is_none() {
+            self.user = other.user.clone();
+        }
+        if self.port.is_none() {
+            self.port = other.port;
+        }
+        if self.tag.is_none() {
+            self.tag = other.tag.clone();
+        }
+    }
+
+    /// Returns the host name that a connection to `name` goes to.
+    fn expanded_hostname(&self, name: &str) -> String {
+        match self.hostname {
+            Some(ref hostname) => expand_hostname(hostname, name),
+            None => name.to_string(),
+        }
+    }
+}
+
+/// Expands the `%h` and `%%` tokens in the `HostName` value
+/// `hostname` for a connection to `name`.
+pub fn expand_hostname(hostname: &str, name: &str) -> String {
+    let mut expanded = String::new();
+    let mut chars = hostname.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            expanded.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('h') => expanded.push_str(name),
+            Some('%') => expanded.push('%'),
+            // Other tokens don't make sense in host names:
+            Some(other) => {
+                expanded.push('%');
+                expanded.push(other);
+            }
+            None => expanded.push('%'),
+        }
+    }
+    expanded
+}
+
+/// What a block of settings applies to.
+enum Selector {
+    /// The hosts matching any of a `Host` line's patterns.
+    Host(Vec<String>),
+    /// The connections that all of a `Match` line's criteria hold for.
+    Match(Vec<Criterion>),
+}
+
+/// One criterion of a `Match` line, like `host *.prod` or `!exec "..."`.
+#[derive(Debug, PartialEq)]
+struct Criterion {
+    negated: bool,
+    /// The criterion's keyword, lowercased.
+    keyword: String,
+    /// The comma-separated patterns or the command that the keyword
+    /// takes; empty for `all`, `canonical` and `final`.
+    argument: String,
+}
+
+/// Parses the arguments of a `Match` line into criteria, or returns
+/// `None` if a criterion is missing its argument.
+fn parse_criteria(args: Vec<String>) -> Option<Vec<Criterion>> {
+    let mut criteria = vec![];
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let keyword = arg.to_lowercase();
+        let (negated, keyword) = match keyword.strip_prefix('!') {
+            Some(keyword) => (true, keyword.to_string()),
+            None => (false, keyword),
+        };
+        let argument = match keyword.as_str() {
+            "all" | "canonical" | "final" => String::new(),
+            _ => args.next()?,
+        };
+        criteria.push(Criterion {
+            negated,
+            keyword,
+            argument,
+        });
+    }
+    Some(criteria)
+}
 
-            let line = line.trim();
-            // Skip comments or blank lines:
-            if line.is_empty() || line.starts_with('#') {
-                continue;
+impl Criterion {
+    /// Evaluates the criterion for a connection to `original`, with
+    /// the settings that were obtained before the `Match` line.
+    ///
+    /// Criteria that depend on the machine ssh runs on can't be
+    /// evaluated when making bookmarks: `exec` is assumed to succeed,
+    /// so the settings of `Match host *.prod exec ...` blocks still
+    /// apply to the matching hosts, while `localnetwork` and
+    /// `canonical` (hostname canonicalization isn't done here) never
+    /// match. `tagged` only sees the `Tag` from the config files.
+    fn matches(&self, original: &str, settings: &Settings) -> bool {
+        let patterns: Vec<&str> = self.argument.split(',').collect();
+        let matched = match self.keyword.as_str() {
+            "all" | "final" | "exec" => true,
+            "host" => {
+                let hostname = settings.expanded_hostname(original);
+                pattern_list_matches(&patterns, &hostname)
             }
+            "originalhost" => pattern_list_matches(&patterns, original),
+            "user" => match settings.user.clone().or_else(local_user) {
+                Some(user) => pattern_list_matches(&patterns, &user),
+                None => false,
+            },
+            "tagged" => match settings.tag {
+                Some(ref tag) => pattern_list_matches(&patterns, tag),
+                None => false,
+            },
+            "localuser" => match local_user() {
+                Some(user) => pattern_list_matches(&patterns, &user),
+                None => false,
+            },
+            _ => false,
+        };
+        matched != self.negated
+    }
+}
+
+fn local_user() -> Option<String> {
+    env::var("USER").ok()
+}
+
+/// A `Host` or `Match` line and the settings on the lines following it.
+struct Block {
+    selector: Selector,
+    protocols: Vec<String>,
+    from: PathBuf,
+    line: Option<usize>,
+    settings: Settings,
+}
 
-            let mut protocols: Vec<&str> = vec!["ssh"];
-            let annotated: Vec<&str> = line.split("#:").collect();
-            if annotated.len() > 1 {
-                protocols = annotated[1].split(',').collect();
+impl Block {
+    /// Returns the host names that a `Host` block declares, leaving out
+    /// negated patterns.
+    fn names(&self) -> Vec<&str> {
+        match self.selector {
+            Selector::Host(ref patterns) => patterns
+                .iter()
+                .filter(|p| !p.starts_with('!'))
+                .map(|p| p.as_str())
+                .collect(),
+            Selector::Match(_) => vec![],
+        }
+    }
+
+    /// Returns the negated patterns of a `Host` block, `!` included.
+    fn exceptions(&self) -> Vec<String> {
+        match self.selector {
+            Selector::Host(ref patterns) => patterns
+                .iter()
+                .filter(|p| p.starts_with('!'))
+                .cloned()
+                .collect(),
+            Selector::Match(_) => vec![],
+        }
+    }
+
+    fn matches(&self, name: &str, settings: &Settings) -> bool {
+        match self.selector {
+            Selector::Host(ref patterns) => {
+                let patterns: Vec<&str> = patterns.iter().map(|p| p.as_str()).collect();
+                pattern_list_matches(&patterns, name)
             }
+            Selector::Match(ref criteria) => criteria.iter().all(|c| c.matches(name, settings)),
+        }
+    }
+}
+
+/// Resolves the settings that ssh would use when connecting to `name`.
+/// For wildcard names, `%h` in the `HostName` is left for the concrete
+/// hosts that the wildcard gets expanded to.
+fn resolve(blocks: &[Block], name: &str) -> Settings {
+    let mut settings = Settings::default();
+    for block in blocks.iter() {
+        if block.matches(name, &settings) {
+            settings.merge(&block.settings);
+        }
+    }
+    if settings.hostname.is_some() && !name.contains(&['*', '?'][..]) {
+        settings.hostname = Some(settings.expanded_hostname(name));
+    }
+    settings
+}
 
-            if annotated[0].to_lowercase().starts_with("host") {
-                let host_entries: Vec<&str> = annotated[0].split_whitespace().skip(1).collect();
-                for proto in protocols.iter() {
-                    hosts.extend(
-                        host_entries
-                            .as_slice()
-                            .iter()
-                            .map(|name| Host::new(name, proto, self.pathname())),
-                    )
+/// Matches a hostname against a list of ssh_config(5) patterns, where
+/// patterns starting with `!` are negated: the list matches if any
+/// pattern matches and no negated pattern does.
+pub fn pattern_list_matches<S: AsRef<str>>(patterns: &[S], name: &str) -> bool {
+    let mut matched = false;
+    for pattern in patterns.iter().map(|p| p.as_ref()) {
+        match pattern.strip_prefix('!') {
+            Some(negated) => {
+                if pattern_matches(negated, name) {
+                    return false;
+                }
+            }
+            None => matched = matched || pattern_matches(pattern, name),
+        }
+    }
+    matched
+}
+
+/// Matches a hostname against an ssh_config(5) pattern, where `*`
+/// matches any number of characters and `?` exactly one. Like in ssh,
+/// the match is case-insensitive.
+pub fn pattern_matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let name: Vec<char> = name.to_lowercase().chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Where to resume after the last `*` if the rest fails to match:
+    let mut backtrack: Option<(usize, usize)> = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, n));
+            p += 1;
+        } else if let Some((star, matched)) = backtrack {
+            p = star + 1;
+            n = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// A single non-empty line of an ssh_config file.
+#[derive(Debug, PartialEq)]
+struct Directive {
+    /// The keyword, lowercased (ssh_config keywords are case-insensitive).
+    keyword: String,
+    /// The arguments, with quotes removed.
+    args: Vec<String>,
+    /// The protocols from a trailing `#:proto1,proto2` annotation, or
+    /// just `ssh` if there is none.
+    protocols: Vec<String>,
+}
+
+/// Splits an ssh_config(5) line into keyword and arguments. Keywords
+/// may be separated from their arguments by whitespace or a single
+/// `=`, and arguments can be double-quoted, in whole or in part, to
+/// contain whitespace.
+///
+/// Returns `None` for blank lines and comments, and `Some(Err(keyword))`
+/// for lines with unbalanced quotes.
+fn tokenize(line: &str) -> Option<::std::result::Result<Directive, String>> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+
+    let keyword_end = line
+        .find(|c: char| c.is_whitespace() || c == '=')
+        .unwrap_or(line.len());
+    let keyword = line[..keyword_end].to_lowercase();
+    let mut rest = line[keyword_end..].trim_start();
+    if let Some(after_equals) = rest.strip_prefix('=') {
+        rest = after_equals.trim_start();
+    }
+
+    let mut args = vec![];
+    let mut protocols = vec![];
+    let mut chars = rest.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '#' {
+            // Everything from here on is a comment; the `#:` kind
+            // annotates the protocols to bookmark the hosts with.
+            if let Some(annotation) = rest[start..].strip_prefix("#:") {
+                protocols = annotation
+                    .split(',')
+                    .map(|p| p.trim())
+                    .filter(|p| !p.is_empty())
+                    .map(|p| p.to_string())
+                    .collect();
+            }
+            break;
+        } else {
+            // Like OpenSSH, quoted parts can start anywhere in a word:
+            let mut arg = String::new();
+            let mut quoted = false;
+            while let Some(&(_, c)) = chars.peek() {
+                if c.is_whitespace() && !quoted {
+                    break;
+                }
+                chars.next();
+                if c == '"' {
+                    quoted = !quoted;
+                } else {
+                    arg.push(c);
+                }
+            }
+            if quoted {
+                return Some(Err(keyword));
+            }
+            args.push(arg);
+        }
+    }
+    if protocols.is_empty() {
+        protocols.push("ssh".to_string());
+    }
+    Some(Ok(Directive {
+        keyword,
+        args,
+        protocols,
+    }))
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+impl ConfigFile for SSHConfigFile {
+    fn pathname(&self) -> &Path {
+        self.pathname.as_path()
+    }
+
+    fn parse_entries<R: BufRead>(&self, file: R) -> Result<Vec<Host>> {
+        let mut including = vec![canonical(self.pathname())];
+        // Settings before the first Host line apply to every host:
+        let mut blocks = vec![Block {
+            selector: Selector::Host(vec!["*".to_string()]),
+            protocols: vec![],
+            from: self.pathname().to_path_buf(),
+            line: None,
+            settings: Settings::default(),
+        }];
+        self.parse_file(self.pathname(), file, &mut including, &mut blocks, 0)?;
+
+        let mut hosts: Vec<Host> = vec![];
+        for block in blocks.iter() {
+            for proto in block.protocols.iter() {
+                for name in block.names() {
+                    let settings = resolve(&blocks, name);
+                    let mut host = Host::new(name, proto, &block.from);
+                    host.line = block.line;
+                    host.hostname = settings.hostname;
+                    host.user = settings.user;
+                    host.port = settings.port;
+                    host.tag = settings.tag;
+                    if host.is_wildcard() {
+                        host.exceptions = block.exceptions();
+                    }
+                    hosts.push(host);
                 }
             }
         }
@@ -58,3 +578,338 @@ fn test_ssh_config() {
     let c = SSHConfigFile::from(PathBuf::from("/tmp"));
     assert_eq!(c.pathname(), Path::new("/tmp"));
 }
+
+#[test]
+fn test_include() {
+    use std::fs;
+    use std::io::Cursor;
+
+    let dir = ::tempfile::tempdir().unwrap();
+    let confd = dir.path().join("config.d");
+    fs::create_dir(&confd).unwrap();
+    fs::write(confd.join("10-work.conf"), "Host work\nInclude other\n").unwrap();
+    fs::write(confd.join("20-home.conf"), "Host home #:mosh\n").unwrap();
+    fs::write(dir.path().join("other"), "Host other\nInclude config.d/*\n").unwrap();
+
+    let config = dir.path().join("config");
+    let c = SSHConfigFile::from(config.clone()).with_include_dir(dir.path());
+    let hosts = c
+        .parse_entries(Cursor::new("Host top\nInclude config.d/*.conf\n"))
+        .unwrap();
+    assert_eq!(
+        hosts,
+        vec![
+            Host::named("top", &config),
+            Host::named("work", &confd.join("10-work.conf")),
+            Host::named("other", &dir.path().join("other")),
+            Host::new("home", "mosh", &confd.join("20-home.conf")),
+            Host::new("home", "mosh", &confd.join("20-home.conf")),
+        ]
+    );
+}
+
+#[test]
+fn test_include_repeated() {
+    use std::fs;
+    use std::io::Cursor;
+
+    let dir = ::tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("deploy"), "User deploy\n").unwrap();
+    fs::write(dir.path().join("broken"), "Host \"unterminated\n").unwrap();
+
+    let c = SSHConfigFile::from(dir.path().join("config")).with_include_dir(dir.path());
+    let hosts = c
+        .parse_entries(Cursor::new(
+            "Host web\nInclude deploy\nHost db\nInclude broken\nInclude deploy\n",
+        ))
+        .unwrap();
+    let users: Vec<(&str, Option<&str>)> = hosts
+        .iter()
+        .map(|h| (h.name.as_str(), h.user.as_deref()))
+        .collect();
+    assert_eq!(users, vec![("web", Some("deploy")), ("db", Some("deploy"))]);
+}
+
+#[test]
+fn test_include_missing() {
+    use std::io::Cursor;
+
+    let c = SSHConfigFile::from(PathBuf::from("/dev/null"));
+    let hosts = c
+        .parse_entries(Cursor::new(
+            "Include /nonexistent/ssh_bookmarker/*\nHost a\n",
+        ))
+        .unwrap();
+    assert_eq!(hosts, vec![Host::named("a", Path::new("/dev/null"))]);
+}
+
+#[test]
+fn test_tokenize() {
+    fn directive(keyword: &str, args: &[&str], protocols: &[&str]) -> Directive {
+        Directive {
+            keyword: keyword.to_string(),
+            args: args.iter().map(|s| s.to_string()).collect(),
+            protocols: protocols.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    let cases: Vec<(&str, Option<::std::result::Result<Directive, String>>)> = vec![
+        ("", None),
+        ("   ", None),
+        ("# Host commented", None),
+        ("  #:mosh", None),
+        ("Host foo", Some(Ok(directive("host", &["foo"], &["ssh"])))),
+        (
+            "Host foo bar",
+            Some(Ok(directive("host", &["foo", "bar"], &["ssh"]))),
+        ),
+        ("Host=foo", Some(Ok(directive("host", &["foo"], &["ssh"])))),
+        (
+            "Host = foo",
+            Some(Ok(directive("host", &["foo"], &["ssh"]))),
+        ),
+        (
+            "  HOST\tfoo  ",
+            Some(Ok(directive("host", &["foo"], &["ssh"]))),
+        ),
+        (
+            "Host \"my box\" other",
+            Some(Ok(directive("host", &["my box", "other"], &["ssh"]))),
+        ),
+        (
+            "Host foo #:mosh, sftp",
+            Some(Ok(directive("host", &["foo"], &["mosh", "sftp"]))),
+        ),
+        (
+            "Host foo # just a comment",
+            Some(Ok(directive("host", &["foo"], &["ssh"]))),
+        ),
+        (
+            "HostName 10.0.0.4",
+            Some(Ok(directive("hostname", &["10.0.0.4"], &["ssh"]))),
+        ),
+        (
+            "Host in\"side\" \"a\"\"b\"",
+            Some(Ok(directive("host", &["inside", "ab"], &["ssh"]))),
+        ),
+        ("Host \"unterminated", Some(Err("host".to_string()))),
+        ("User in\"side", Some(Err("user".to_string()))),
+    ];
+    for (line, expected) in cases {
+        assert_eq!(tokenize(line), expected, "tokenizing {:?}", line);
+    }
+}
+
+#[test]
+fn test_host_keywords() {
+    let from = Path::new("fixtures/host_keywords.config");
+    let c = SSHConfigFile::from(from.to_path_buf());
+    let hosts = c
+        .parse_entries(include_str!("fixtures/host_keywords.config").as_bytes())
+        .unwrap();
+    let names: Vec<(&str, &str)> = hosts
+        .iter()
+        .map(|h| (h.name.as_str(), h.protocol.as_str()))
+        .collect();
+    assert_eq!(
+        names,
+        vec![
+            ("plain", "ssh"),
+            ("lowercase", "ssh"),
+            ("other", "ssh"),
+            ("lowercase", "mosh"),
+            ("other", "mosh"),
+            ("equals", "ssh"),
+            ("spaced-equals", "ssh"),
+            ("my box", "ssh"),
+            ("unquoted", "ssh"),
+        ]
+    );
+    assert_eq!(hosts[0].hostname, Some("10.0.0.4".to_string()));
+    assert_eq!(hosts[7].hostname, Some("my box.example.com".to_string()));
+}
+
+#[test]
+fn test_bad_quoting() {
+    let c = SSHConfigFile::from(PathBuf::from("/dev/null"));
+    let err = c
+        .parse_entries("Host ok\nHost \"oops\n".as_bytes())
+        .unwrap_err();
+    match *err.kind() {
+        ErrorKind::ConfigFormat(_, lineno, _) => assert_eq!(lineno, 2),
+        ref other => panic!("unexpected error {:?}", other),
+    }
+}
+
+#[test]
+fn test_bad_quoting_elsewhere() {
+    let from = Path::new("fixtures/bad_quoting.config");
+    let c = SSHConfigFile::from(from.to_path_buf());
+    let hosts = c
+        .parse_entries(include_str!("fixtures/bad_quoting.config").as_bytes())
+        .unwrap();
+    let names: Vec<&str> = hosts.iter().map(|h| h.name()).collect();
+    assert_eq!(names, vec!["before", "after"]);
+    assert_eq!(hosts[0].user, Some("me".to_string()));
+    assert_eq!(hosts[1].hostname, Some("after.example.com".to_string()));
+}
+
+#[test]
+fn test_pattern_matches() {
+    assert!(pattern_matches("*", "anything"));
+    assert!(pattern_matches("*.example.com", "db1.example.com"));
+    assert!(pattern_matches("DB?.example.com", "db1.EXAMPLE.com"));
+    assert!(pattern_matches("*a*b", "xxaxxb"));
+    assert!(!pattern_matches("*.example.com", "example.com"));
+    assert!(!pattern_matches("db?", "db10"));
+    assert!(pattern_matches("exact", "exact"));
+}
+
+#[test]
+fn test_host_settings() {
+    let from = Path::new("/dev/null");
+    let c = SSHConfigFile::from(from.to_path_buf());
+    let hosts = c
+        .parse_entries(
+            r#"
+Host bastion #:ssh,mosh
+    HostName %h.example.com
+    User deploy
+    Port 2222
+    Port 2223
+Host web
+    Port 80
+Match host web
+    User nobody
+Host *
+    User fallback
+    Port 22
+"#
+            .as_bytes(),
+        )
+        .unwrap();
+    let urls: Vec<String> = hosts.iter().map(|h| h.url()).collect();
+    assert_eq!(
+        urls,
+        vec![
+            "ssh://deploy@bastion.example.com:2222",
+            "mosh://deploy@bastion.example.com:2222",
+            "ssh://nobody@web:80",
+            "ssh://fallback@*:22",
+        ]
+    );
+}
+
+#[test]
+fn test_bad_port() {
+    let c = SSHConfigFile::from(PathBuf::from("/dev/null"));
+    let hosts = c
+        .parse_entries("Host ok\n  Port ssh\n  Port 2222\nHost other\n".as_bytes())
+        .unwrap();
+    let urls: Vec<String> = hosts.iter().map(|h| h.url()).collect();
+    assert_eq!(urls, vec!["ssh://ok:2222", "ssh://other"]);
+}
+
+#[test]
+fn test_expanded_hostname() {
+    let settings = |hostname: &str| Settings {
+        hostname: Some(hostname.to_string()),
+        ..Settings::default()
+    };
+    assert_eq!(
+        settings("%h.example.com").expanded_hostname("web"),
+        "web.example.com"
+    );
+    assert_eq!(settings("%%h-%h%%").expanded_hostname("web"), "%h-web%");
+    assert_eq!(settings("%d/%").expanded_hostname("web"), "%d/%");
+    assert_eq!(Settings::default().expanded_hostname("web"), "web");
+}
+
+#[test]
+fn test_pattern_list_matches() {
+    assert!(pattern_list_matches(
+        &["*.prod", "!bastion.prod"],
+        "db1.prod"
+    ));
+    assert!(!pattern_list_matches(
+        &["*.prod", "!bastion.prod"],
+        "bastion.prod"
+    ));
+    assert!(!pattern_list_matches(&["!bastion.prod"], "db1.prod"));
+    assert!(!pattern_list_matches(&["*.prod"], "db1.staging"));
+}
+
+#[test]
+fn test_negated_hosts() {
+    let from = Path::new("/dev/null");
+    let c = SSHConfigFile::from(from.to_path_buf());
+    let hosts = c
+        .parse_entries(
+            r#"
+Host web !web-legacy db
+    User admin
+Host * !db
+    Port 2222
+"#
+            .as_bytes(),
+        )
+        .unwrap();
+    let urls: Vec<String> = hosts.iter().map(|h| h.url()).collect();
+    assert_eq!(
+        urls,
+        vec!["ssh://admin@web:2222", "ssh://admin@db", "ssh://*:2222"]
+    );
+}
+
+#[test]
+fn test_match_blocks() {
+    let from = Path::new("/dev/null");
+    let c = SSHConfigFile::from(from.to_path_buf());
+    let hosts = c
+        .parse_entries(
+            r#"
+Host db1 db2 bastion
+    HostName %h.prod
+Host web
+Match host *.prod,!bastion.prod exec "nc -z %h 22"
+    User deploy
+Match originalhost bastion
+    Port 2200
+Match !host *.prod
+    User nobody
+Match host *.prod localnetwork 10.0.0.0/8
+    Port 1
+Match all
+    Port 22
+"#
+            .as_bytes(),
+        )
+        .unwrap();
+    let urls: Vec<String> = hosts.iter().map(|h| h.url()).collect();
+    assert_eq!(
+        urls,
+        vec![
+            "ssh://deploy@db1.prod:22",
+            "ssh://deploy@db2.prod:22",
+            "ssh://bastion.prod:2200",
+            "ssh://nobody@web:22",
+        ]
+    );
+
+    let tagged = c
+        .parse_entries(
+            "Host db\n    Tag prod\nHost web\nMatch tagged prod\n    User dba\n".as_bytes(),
+        )
+        .unwrap();
+    assert_eq!(tagged[0].tag, Some("prod".to_string()));
+    assert_eq!(tagged[0].url(), "ssh://dba@db");
+    assert_eq!(tagged[1].url(), "ssh://web");
+
+    let err = c
+        .parse_entries("Host ok\nMatch host\n".as_bytes())
+        .unwrap_err();
+    match *err.kind() {
+        ErrorKind::ConfigFormat(_, lineno, _) => assert_eq!(lineno, 2),
+        ref other => panic!("unexpected error {:?}", other),
+    }
+}
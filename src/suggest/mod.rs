@@ -0,0 +1,111 @@
+use bookmarks::stable_id;
+use serde_json::Value;
+use std::collections::HashSet;
+use Host;
+
+/// Returns the hosts whose names contain `query`, best matches first,
+/// like the LaunchBar action's `Entry#relevance`: hosts where the query
+/// matches closer to the start of the name come first, and hosts that
+/// match at the same place stay in the order they were found in. Each
+/// name and protocol is only suggested once.
+pub fn suggestions<'a>(hosts: &[&'a Host], query: &str) -> Vec<&'a Host> {
+    let mut seen = HashSet::new();
+    let mut matches: Vec<(usize, &Host)> = hosts
+        .iter()
+        .filter_map(|host| host.name.find(query).map(|index| (index, *host)))
+        .filter(|&(_, host)| seen.insert((&host.name, &host.protocol)))
+        .collect();
+    matches.sort_by_key(|&(index, _)| index);
+    matches.into_iter().map(|(_, host)| host).collect()
+}
+
+/// Returns the LaunchBar items for `hosts`. Their titles are the URLs
+/// that the action opens.
+pub fn launchbar_items(hosts: &[&Host]) -> Value {
+    let items: Vec<Value> = hosts
+        .iter()
+        .map(|host| {
+            json!({
+                "title": host.url(),
+                "label": host.name,
+                "actionRunsInBackground": true,
+                "badge": host.protocol,
+                "icon": "font-awesome:terminal",
+            })
+        })
+        .collect();
+    Value::Array(items)
+}
+
+/// Returns the Alfred Script Filter output for `hosts`, with the URL
+/// as the argument that's passed on.
+pub fn alfred_items(hosts: &[&Host]) -> Value {
+    let items: Vec<Value> = hosts
+        .iter()
+        .map(|host| {
+            json!({
+                "uid": stable_id(host),
+                "title": format!("{} ({})", host.name, host.protocol),
+                "subtitle": host.url(),
+                "arg": host.url(),
+                "autocomplete": host.name,
+            })
+        })
+        .collect();
+    json!({ "items": items })
+}
+
+#[test]
+fn test_suggestions() {
+    use std::path::Path;
+
+    let from = Path::new("/dev/null");
+    let hosts = [
+        Host::named("web.example.com", from),
+        Host::named("db-web", from),
+        Host::named("mail", from),
+        Host::new("web.example.com", "mosh", from),
+        Host::named("webmail", from),
+        Host::named("web.example.com", Path::new("/etc/hosts")),
+    ];
+    let refs: Vec<&Host> = hosts.iter().collect();
+    let found: Vec<String> = suggestions(&refs, "web").iter().map(|h| h.url()).collect();
+    assert_eq!(
+        found,
+        vec![
+            "ssh://web.example.com",
+            "mosh://web.example.com",
+            "ssh://webmail",
+            "ssh://db-web",
+        ]
+    );
+    assert!(suggestions(&refs, "WEB").is_empty());
+}
+
+#[test]
+fn test_suggestion_items() {
+    use std::path::Path;
+
+    let mut host = Host::new("bastion", "mosh", Path::new("/dev/null"));
+    host.port = Some(2222);
+    assert_eq!(
+        launchbar_items(&[&host]),
+        json!([{
+            "title": "mosh://bastion:2222",
+            "label": "bastion",
+            "actionRunsInBackground": true,
+            "badge": "mosh",
+            "icon": "font-awesome:terminal",
+        }])
+    );
+    assert_eq!(
+        alfred_items(&[&host]),
+        json!({"items": [{
+            "uid": stable_id(&host),
+            "title": "bastion (mosh)",
+            "subtitle": "mosh://bastion:2222",
+            "arg": "mosh://bastion:2222",
+            "autocomplete": "bastion",
+        }]})
+    );
+}
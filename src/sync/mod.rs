@@ -0,0 +1,387 @@
+use errors::*;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::prelude::*;
+use std::io::ErrorKind as IoErrorKind;
+use std::path::{Component, Path};
+
+/// The file in the output directory that lists the bookmark files
+/// ssh_bookmarker created there, so that it only ever removes its own
+/// files.
+pub const MANIFEST: &str = ".ssh_bookmarker_manifest";
+
+/// A bookmark file's contents and the URL that it opens, if it's
+/// about a single host.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bookmark {
+    pub url: Option<String>,
+    pub contents: String,
+}
+
+/// Maps bookmark file names to their bookmarks.
+pub type Bookmarks = BTreeMap<String, Bookmark>;
+
+/// The changes that bring an output directory up to date.
+#[derive(Debug, Default, PartialEq)]
+pub struct Plan {
+    /// Bookmark files that don't exist yet.
+    pub added: Bookmarks,
+    /// Bookmark files whose contents are out of date.
+    pub changed: Bookmarks,
+    /// Bookmark files that we created earlier but that aren't wanted
+    /// anymore.
+    pub removed: Vec<String>,
+    /// All the bookmark files that should be in the directory.
+    pub manifest: Vec<String>,
+}
+
+impl Plan {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.changed.is_empty() && self.removed.is_empty()
+    }
+
+    /// Describes the changes one per line, like a diff: `+` for added,
+    /// `~` for changed and `-` for removed bookmarks.
+    pub fn write_summary<W: Write>(&self, out: &mut W) -> Result<()> {
+        let lines = self
+            .added
+            .iter()
+            .map(|change| ('+', change))
+            .chain(self.changed.iter().map(|change| ('~', change)));
+        for (sign, (name, bookmark)) in lines {
+            match bookmark.url {
+                Some(ref url) => writeln!(out, "{} {}\t{}", sign, name, url)?,
+                None => writeln!(out, "{} {}", sign, name)?,
+            }
+        }
+        for name in self.removed.iter() {
+            writeln!(out, "- {}", name)?;
+        }
+        Ok(())
+    }
+
+    /// Describes the changes as JSON lines with the `action` (`add`,
+    /// `change` or `remove`), the `filename` and the `url`, which is
+    /// `null` for removed bookmarks.
+    pub fn write_json_lines<W: Write>(&self, out: &mut W) -> Result<()> {
+        let changes = self
+            .added
+            .iter()
+            .map(|(name, bookmark)| ("add", name, bookmark.url.as_ref()))
+            .chain(
+                self.changed
+                    .iter()
+                    .map(|(name, bookmark)| ("change", name, bookmark.url.as_ref())),
+            )
+            .chain(self.removed.iter().map(|name| ("remove", name, None)));
+        for (action, name, url) in changes {
+            let line = json!({"action": action, "filename": name, "url": url});
+            writeln!(out, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads the names of the files that ssh_bookmarker created in `dir`.
+/// Without a manifest, none of the files there are known to be ours,
+/// so none of them get removed.
+fn created_files(dir: &Path) -> Result<Vec<String>> {
+    match fs::read_to_string(dir.join(MANIFEST)) {
+        Ok(manifest) => Ok(manifest
+            .lines()
+            .filter(|l| !l.is_empty())
+            .map(|l| l.to_string())
+            .collect()),
+        Err(ref e) if e.kind() == IoErrorKind::NotFound => Ok(vec![]),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Returns whether the bookmark file `name` stays inside the output
+/// directory: it can be in folders, but can't be absolute or go up.
+fn is_inside(name: &str) -> bool {
+    !name.is_empty()
+        && Path::new(name)
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+}
+
+/// Fails unless all of `names` stay inside the output directory.
+fn check_inside<'a, I: IntoIterator<Item = &'a String>>(names: I) -> Result<()> {
+    for name in names {
+        if !is_inside(name) {
+            bail!(ErrorKind::BookmarkPath(name.to_string()));
+        }
+    }
+    Ok(())
+}
+
+/// Removes the folders between `dir` and the removed bookmark at
+/// `path` that are empty now.
+fn remove_empty_folders(dir: &Path, path: &Path) {
+    for folder in path.ancestors().skip(1) {
+        // Folders that still have something in them can't be removed,
+        // and neither can anything above them:
+        if folder == dir || !folder.starts_with(dir) || fs::remove_dir(folder).is_err() {
+            break;
+        }
+    }
+}
+
+/// Compares the `wanted` bookmarks with what's in `dir`.
+pub fn plan(dir: &Path, wanted: Bookmarks) -> Result<Plan> {
+    check_inside(wanted.keys())?;
+    let mut plan = Plan::default();
+    for (name, bookmark) in wanted {
+        match fs::read_to_string(dir.join(&name)) {
+            Ok(ref existing) if *existing == bookmark.contents => {}
+            Ok(_) => {
+                plan.changed.insert(name.clone(), bookmark);
+            }
+            Err(ref e) if e.kind() == IoErrorKind::NotFound => {
+                plan.added.insert(name.clone(), bookmark);
+            }
+            Err(e) => return Err(e.into()),
+        }
+        plan.manifest.push(name);
+    }
+    for name in created_files(dir)? {
+        if !is_inside(&name) {
+            eprintln!(
+                "Warning: {:?} from the manifest in {:?} is outside of it, leaving it alone",
+                name, dir
+            );
+            continue;
+        }
+        if plan.manifest.binary_search(&name).is_err() && dir.join(&name).exists() {
+            plan.removed.push(name);
+        }
+    }
+    plan.removed.sort();
+    plan.removed.dedup();
+    Ok(plan)
+}
+
+/// Writes the new and changed bookmarks of `plan` to `dir`, removes
+/// the stale ones (and the folders that they leave empty) and records
+/// what's there in the manifest.
+pub fn apply(dir: &Path, plan: &Plan) -> Result<()> {
+    check_inside(
+        plan.added
+            .keys()
+            .chain(plan.changed.keys())
+            .chain(plan.removed.iter()),
+    )?;
+    fs::create_dir_all(dir).chain_err(|| format!("Couldn't create output directory {:?}", dir))?;
+    for (name, bookmark) in plan.added.iter().chain(plan.changed.iter()) {
+        let path = dir.join(name);
+        if let Some(folder) = path.parent() {
+            fs::create_dir_all(folder)
+                .chain_err(|| format!("Couldn't create folder {:?}", folder))?;
+        }
+        fs::write(&path, &bookmark.contents)
+            .chain_err(|| format!("Couldn't write bookmark {:?}", path))?;
+    }
+    for name in plan.removed.iter() {
+        let path = dir.join(name);
+        fs::remove_file(&path).chain_err(|| format!("Couldn't remove bookmark {:?}", path))?;
+        remove_empty_folders(dir, &path);
+    }
+
+    let mut manifest = plan.manifest.join("\n");
+    manifest.push('\n');
+    let manifest_path = dir.join(MANIFEST);
+    if fs::read_to_string(&manifest_path).ok().as_ref() != Some(&manifest) {
+        fs::write(&manifest_path, manifest)
+            .chain_err(|| format!("Couldn't write manifest {:?}", manifest_path))?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_sync() {
+    use bookmarks::{BookmarkFile, BookmarkWriter, Webloc};
+    use std::path::PathBuf;
+    use Host;
+
+    let dir = ::tempfile::tempdir().unwrap();
+    let output = dir.path().join("bookmarks");
+    let from = PathBuf::from("/dev/null");
+    let one = Host::named("one", &from);
+    let two = Host::new("two", "mosh", &from);
+
+    // A fresh directory gets everything:
+    let wanted = Webloc.bookmarks(&[&one, &two]).unwrap();
+    let first = plan(&output, wanted.clone()).unwrap();
+    assert_eq!(first.added, wanted);
+    assert!(first.changed.is_empty() && first.removed.is_empty());
+    apply(&output, &first).unwrap();
+    assert_eq!(
+        fs::read_to_string(output.join("one (ssh).webloc")).unwrap(),
+        Webloc.contents(&one)
+    );
+    assert_eq!(
+        wanted["one (ssh).webloc"].url.as_ref().unwrap(),
+        "ssh://one"
+    );
+
+    // Nothing to do if nothing changed:
+    assert!(plan(&output, wanted).unwrap().is_empty());
+
+    // Stale and changed bookmarks are handled, other files are left alone:
+    fs::write(output.join("notes.txt"), "mine").unwrap();
+    fs::write(output.join("mine (ssh).webloc"), "also mine").unwrap();
+    let mut two_ported = two.clone();
+    two_ported.port = Some(2222);
+    let three = Host::named("three", &from);
+    let second = plan(&output, Webloc.bookmarks(&[&two_ported, &three]).unwrap()).unwrap();
+    assert_eq!(
+        second.added.keys().collect::<Vec<_>>(),
+        vec!["three (ssh).webloc"]
+    );
+    assert_eq!(
+        second.changed.keys().collect::<Vec<_>>(),
+        vec!["two (mosh).webloc"]
+    );
+    assert_eq!(second.removed, vec!["one (ssh).webloc"]);
+    apply(&output, &second).unwrap();
+
+    let mut files: Vec<String> = fs::read_dir(&output)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().into_string().unwrap())
+        .collect();
+    files.sort();
+    assert_eq!(
+        files,
+        vec![
+            MANIFEST,
+            "mine (ssh).webloc",
+            "notes.txt",
+            "three (ssh).webloc",
+            "two (mosh).webloc"
+        ]
+    );
+}
+
+#[test]
+fn test_sync_without_manifest() {
+    let dir = ::tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("mine (ssh).webloc"), "mine").unwrap();
+    let mut wanted = Bookmarks::new();
+    wanted.insert(
+        "new (ssh).webloc".to_string(),
+        Bookmark {
+            url: None,
+            contents: "x".to_string(),
+        },
+    );
+    let first = plan(dir.path(), wanted).unwrap();
+    assert!(first.removed.is_empty());
+    apply(dir.path(), &first).unwrap();
+    assert_eq!(
+        fs::read_to_string(dir.path().join("mine (ssh).webloc")).unwrap(),
+        "mine"
+    );
+    assert_eq!(
+        fs::read_to_string(dir.path().join(MANIFEST)).unwrap(),
+        "new (ssh).webloc\n"
+    );
+
+    // Only what's in the manifest is ours to remove later:
+    let second = plan(dir.path(), Bookmarks::new()).unwrap();
+    assert_eq!(second.removed, vec!["new (ssh).webloc"]);
+}
+
+#[test]
+fn test_plan_output() {
+    let mut plan = Plan::default();
+    let bookmark = |url: &str| Bookmark {
+        url: Some(url.to_string()),
+        contents: String::new(),
+    };
+    plan.added
+        .insert("new (ssh).webloc".to_string(), bookmark("ssh://new"));
+    plan.changed.insert(
+        "moved (mosh).webloc".to_string(),
+        bookmark("mosh://moved:2222"),
+    );
+    plan.removed.push("old \"quoted\" (ssh).webloc".to_string());
+
+    let mut summary = vec![];
+    plan.write_summary(&mut summary).unwrap();
+    assert_eq!(
+        String::from_utf8(summary).unwrap(),
+        "+ new (ssh).webloc\tssh://new\n\
+         ~ moved (mosh).webloc\tmosh://moved:2222\n\
+         - old \"quoted\" (ssh).webloc\n"
+    );
+
+    let mut json = vec![];
+    plan.write_json_lines(&mut json).unwrap();
+    assert_eq!(
+        String::from_utf8(json).unwrap(),
+        r#"{"action":"add","filename":"new (ssh).webloc","url":"ssh://new"}
+{"action":"change","filename":"moved (mosh).webloc","url":"mosh://moved:2222"}
+{"action":"remove","filename":"old \"quoted\" (ssh).webloc","url":null}
+"#
+    );
+}
+
+#[test]
+fn test_sync_folders() {
+    let dir = ::tempfile::tempdir().unwrap();
+    let bookmark = Bookmark {
+        url: None,
+        contents: "x".to_string(),
+    };
+    let mut wanted = Bookmarks::new();
+    wanted.insert("a/b/one.webloc".to_string(), bookmark.clone());
+    wanted.insert("a/two.webloc".to_string(), bookmark.clone());
+    wanted.insert("c/three.webloc".to_string(), bookmark.clone());
+    apply(dir.path(), &plan(dir.path(), wanted).unwrap()).unwrap();
+    assert!(dir.path().join("a/b/one.webloc").exists());
+    fs::write(dir.path().join("c/notes.txt"), "mine").unwrap();
+
+    let mut wanted = Bookmarks::new();
+    wanted.insert("a/two.webloc".to_string(), bookmark);
+    let second = plan(dir.path(), wanted).unwrap();
+    assert_eq!(second.removed, vec!["a/b/one.webloc", "c/three.webloc"]);
+    apply(dir.path(), &second).unwrap();
+    assert!(!dir.path().join("a/b").exists());
+    assert!(dir.path().join("a/two.webloc").exists());
+    assert!(dir.path().join("c/notes.txt").exists());
+}
+
+#[test]
+fn test_sync_outside() {
+    let dir = ::tempfile::tempdir().unwrap();
+    let output = dir.path().join("bookmarks");
+    fs::create_dir(&output).unwrap();
+    let bookmark = Bookmark {
+        url: None,
+        contents: "x".to_string(),
+    };
+    for name in &[
+        "../escape.webloc",
+        "a/../../escape.webloc",
+        "/tmp/escape.webloc",
+    ] {
+        let mut wanted = Bookmarks::new();
+        wanted.insert(name.to_string(), bookmark.clone());
+        match *plan(&output, wanted).unwrap_err().kind() {
+            ErrorKind::BookmarkPath(ref path) => assert_eq!(path, name),
+            ref other => panic!("unexpected error {:?}", other),
+        }
+    }
+
+    // A manifest that points elsewhere doesn't get anything removed:
+    fs::write(dir.path().join("escape.webloc"), "mine").unwrap();
+    fs::write(output.join(MANIFEST), "../escape.webloc\n").unwrap();
+    let planned = plan(&output, Bookmarks::new()).unwrap();
+    assert!(planned.removed.is_empty());
+
+    let mut sneaky = Plan::default();
+    sneaky.removed.push("../escape.webloc".to_string());
+    assert!(apply(&output, &sneaky).is_err());
+    assert!(dir.path().join("escape.webloc").exists());
+}